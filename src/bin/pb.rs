@@ -1,22 +1,27 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command as ExecCommand;
 
 use directories::ProjectDirs;
 use exitfailure::ExitFailure;
-use failure::{err_msg, ResultExt};
+use failure::{err_msg, format_err, ResultExt};
 use indicatif::{ProgressBar, ProgressStyle};
+use log::warn;
 use structopt::StructOpt;
 
-use bonnibel::Project;
+use bonnibel::{
+    discover, display_value, edit_config, flow_list, known_build_dirs, menuconfig, migrate,
+    register_build_dir, scalar, scaffold, write_atomic, BuildLock, ConfigFormat, Origin, Project,
+    State, UserConfig, SCHEMA_VERSION,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "Bonnibel")]
 struct Bonnibel {
-    /// The modules file to read from (default "modules.yaml")
+    /// The modules file to read from (default: found in the current directory or a parent)
     #[structopt(parse(from_os_str), short = "f", long = "file")]
     config_file: Option<PathBuf>,
 
-    /// The build directory to use (default "build")
+    /// The build directory to use (default: the current build directory, or "build")
     #[structopt(parse(from_os_str), short = "d", long = "dir")]
     build_dir: Option<PathBuf>,
 
@@ -27,32 +32,195 @@ struct Bonnibel {
     command: Command,
 }
 
+#[derive(Debug, StructOpt)]
+enum ModuleCommand {
+    /// Add a new module
+    #[structopt(name = "add")]
+    Add {
+        /// The name of the new module
+        name: String,
+
+        /// The kind of module, "lib" or "exe"
+        #[structopt(short = "k", long = "kind", default_value = "lib")]
+        kind: String,
+
+        /// The target to build an executable for
+        #[structopt(short = "t", long = "target")]
+        target: Option<String>,
+
+        /// The module's output file (default "<name>.a" or "<name>.elf")
+        #[structopt(short = "o", long = "output")]
+        output: Option<String>,
+
+        /// A source file, may be given multiple times
+        #[structopt(short = "s", long = "source")]
+        source: Vec<String>,
+
+        /// A dependency, may be given multiple times
+        #[structopt(short = "D", long = "dep")]
+        depends: Vec<String>,
+    },
+
+    /// Remove a module
+    #[structopt(name = "rm")]
+    Remove {
+        /// The name of the module to remove
+        name: String,
+    },
+
+    /// Make one module depend on another
+    #[structopt(name = "add-dep")]
+    AddDep {
+        /// The module to add the dependency to
+        module: String,
+
+        /// The module to depend on
+        dep: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    /// Print the value of a variable
+    #[structopt(name = "get")]
+    Get {
+        /// The variable to print
+        name: String,
+    },
+
+    /// Set variables and regenerate the build files
+    #[structopt(name = "set")]
+    Set {
+        /// A series of name=value, name:=<yaml value>, name+=item or name-=item
+        #[structopt(required = true)]
+        vars: Vec<String>,
+    },
+
+    /// Unset variables and regenerate the build files
+    #[structopt(name = "unset")]
+    Unset {
+        /// The variables to unset
+        #[structopt(required = true)]
+        names: Vec<String>,
+    },
+
+    /// List the value of every variable
+    #[structopt(name = "list")]
+    List,
+}
+
 #[derive(Debug, StructOpt)]
 enum Command {
     /// Initialize the build directory and options
     #[structopt(name = "init")]
     Init {
-        /// A series of name=value pairs
+        /// A preset from the project file to start from
+        #[structopt(short = "p", long = "preset")]
+        preset: Option<String>,
+
+        /// A YAML or name=value file of variables, may be given multiple times
+        #[structopt(parse(from_os_str), short = "F", long = "vars-file", number_of_values = 1)]
+        vars_files: Vec<PathBuf>,
+
+        /// Run every probe again instead of using cached results
+        #[structopt(long = "reprobe")]
+        reprobe: bool,
+
+        /// Initialize a build directory for every combination of the
+        /// name=value1,value2 axes that follow, so give any other variables
+        /// before this
+        #[structopt(long = "matrix", min_values = 1)]
+        matrix: Vec<String>,
+
+        /// The build directory for each combination of --matrix, with {name}
+        /// replaced by each axis' value (default: the build directory, suffixed with each value)
+        #[structopt(long = "matrix-dir")]
+        matrix_dir: Option<String>,
+
+        /// A series of name=value, name:=<yaml value>, name+=item or name-=item
         vars: Vec<String>,
     },
 
     /// Regenerate the build files
     #[structopt(name = "generate")]
-    Regenerate,
+    Regenerate {
+        /// Regenerate every known build directory of the project
+        #[structopt(long = "all")]
+        all: bool,
+    },
+
+    /// Show or change the variables of an initialized build directory
+    #[structopt(name = "config")]
+    Config {
+        /// Show where each setting and variable comes from
+        #[structopt(long = "show-origin")]
+        show_origin: bool,
+
+        /// What to do, "list" if not given
+        #[structopt(subcommand)]
+        command: Option<ConfigCommand>,
+    },
 
     /// Synchronize external packages
     #[structopt(name = "sync")]
     Sync {
-        /// Location of the download cache (default ~/.cache/bonnibel)
+        /// Location of the download cache (default: from the user config, or ~/.cache/bonnibel)
         #[structopt(parse(from_os_str), short = "c", long = "cache")]
         cache: Option<PathBuf>,
     },
 
+    /// Edit the variables of an initialized build directory in a terminal menu
+    #[structopt(name = "menuconfig")]
+    Menuconfig,
+
+    /// Rewrite the project file into the current schema
+    #[structopt(name = "migrate")]
+    Migrate {
+        /// Only report the changes that would be made
+        #[structopt(short = "n", long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Edit the modules in the project file
+    #[structopt(name = "module")]
+    Module {
+        #[structopt(subcommand)]
+        command: ModuleCommand,
+    },
+
+    /// Create a new project with a starter set of templates
+    #[structopt(name = "new")]
+    New {
+        /// The directory to create the project in (default ".")
+        #[structopt(parse(from_os_str))]
+        dir: Option<PathBuf>,
+
+        /// The kind of starter project: "c", "cpp" or "asm-kernel"
+        #[structopt(short = "t", long = "template", default_value = "c")]
+        template: String,
+    },
+
+    /// Print the JSON Schema for project files
+    #[structopt(name = "schema")]
+    Schema,
+
+    /// List the variables the project declares
+    #[structopt(name = "vars")]
+    Vars,
+
+    /// Show which template is used for each module and target, and why
+    #[structopt(name = "templates")]
+    Templates,
+
     /// Run the build via Ninja
     ///
     /// This command is mainly a shortcut for invoking Ninja to run the build.
     #[structopt(name = "build")]
-    Build,
+    Build {
+        /// Build every known build directory of the project
+        #[structopt(long = "all")]
+        all: bool,
+    },
 
     /// Run the clean via Ninja
     ///
@@ -61,6 +229,271 @@ enum Command {
     Clean,
 }
 
+/// Options to `pb init` used for every build directory it sets up.
+struct InitOptions {
+    preset: Option<String>,
+    vars_files: Vec<PathBuf>,
+    reprobe: bool,
+}
+
+fn init(
+    proj: &mut Project,
+    user: &UserConfig,
+    build_dir: &Path,
+    opts: &InitOptions,
+    vars: Vec<String>,
+) -> Result<(), failure::Error> {
+    let _lock = BuildLock::acquire(build_dir)?;
+    proj.apply_user_config(user)?;
+    proj.import_env()?;
+    if let Some(preset) = &opts.preset {
+        proj.apply_preset(preset)?;
+    }
+    for file in opts.vars_files.iter() {
+        proj.read_vars_file(file)?;
+    }
+    proj.parse_vars(vars)?;
+
+    let previous = match State::exists(build_dir) && !opts.reprobe {
+        true => State::load(build_dir)?.probes,
+        false => Default::default(),
+    };
+    proj.run_probes(&previous)?;
+    proj.resolve()?;
+    proj.detect_toolchains()?;
+
+    proj.initialize(build_dir)?;
+    proj.generate(build_dir)
+}
+
+/// Regenerate an initialized build directory after its vars have changed,
+/// running again any probes whose results they change.
+fn reconfigure(proj: &mut Project, build_dir: &Path) -> Result<(), failure::Error> {
+    let previous = State::load(build_dir)?.probes;
+    proj.run_probes(&previous)?;
+    proj.resolve()?;
+    proj.detect_toolchains()?;
+    proj.initialize(build_dir)?;
+    proj.generate(build_dir)
+}
+
+/// Remember a newly initialized build directory for `--all`. Not being able
+/// to isn't worth failing `pb init` over.
+fn register(dirs: &ProjectDirs, proj: &Project, build_dir: &Path) {
+    if let Err(e) = register_build_dir(dirs, &proj.config_file, build_dir) {
+        warn!("Couldn't record {} as a build directory: {}", build_dir.display(), e);
+    }
+}
+
+fn all_build_dirs(dirs: &ProjectDirs, proj: &Project) -> Result<Vec<PathBuf>, failure::Error> {
+    let build_dirs = known_build_dirs(dirs, &proj.config_file)?;
+    if build_dirs.is_empty() {
+        return Err(format_err!(
+            "no build directories of {} are known, run `pb init` first", proj.config_file.display()));
+    }
+    Ok(build_dirs)
+}
+
+/// Parse a `--matrix` axis: a variable and the values to build with.
+fn parse_axis(arg: &str) -> Result<(String, Vec<String>), failure::Error> {
+    let (name, values) = arg
+        .split_once('=')
+        .ok_or_else(|| format_err!("--matrix axis '{}' should be name=value1,value2", arg))?;
+    if name.ends_with(['+', '-', ':']) {
+        return Err(format_err!(
+            "--matrix axis '{}' should be name=value1,value2, give other variables before --matrix", arg));
+    }
+
+    let values: Vec<String> = values
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+        .collect();
+    if name.is_empty() || values.is_empty() {
+        return Err(format_err!("--matrix axis '{}' should be name=value1,value2", arg));
+    }
+    Ok((name.to_string(), values))
+}
+
+/// Every combination of one value from each axis, varying the last axis
+/// fastest.
+fn combinations(axes: &[(String, Vec<String>)]) -> Vec<Vec<(String, String)>> {
+    let mut result = vec![Vec::new()];
+    for (name, values) in axes {
+        result = result
+            .into_iter()
+            .flat_map(|combination: Vec<(String, String)>| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((name.to_string(), value.to_string()));
+                    combination
+                })
+            })
+            .collect();
+    }
+    result
+}
+
+/// The build directory for one combination of a matrix: `pattern` with
+/// each `{name}` replaced, relative to the project root, or by default the
+/// base build directory suffixed with the value of each axis that has more
+/// than one.
+fn matrix_build_dir(
+    base: &Path,
+    root: &Path,
+    pattern: Option<&str>,
+    axes: &[(String, Vec<String>)],
+    combination: &[(String, String)],
+) -> Result<PathBuf, failure::Error> {
+    let pattern = match pattern {
+        Some(pattern) => pattern,
+        None => {
+            let values: Vec<&str> = combination
+                .iter()
+                .zip(axes)
+                .filter(|(_, (_, values))| values.len() > 1)
+                .map(|((_, v), _)| v.as_str())
+                .collect();
+            if values.is_empty() {
+                return Ok(base.to_path_buf());
+            }
+            let name = base.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+            return Ok(base.with_file_name(format!("{}-{}", name, values.join("-"))));
+        },
+    };
+
+    let mut dir = pattern.to_string();
+    for (name, value) in combination {
+        dir = dir.replace(&format!("{{{}}}", name), value);
+    }
+    if dir.contains('{') {
+        return Err(format_err!("--matrix-dir '{}' names something that isn't an axis", pattern));
+    }
+    Ok(root.join(dir))
+}
+
+fn regenerate(proj: &mut Project, build_dir: &Path) -> Result<(), failure::Error> {
+    let _lock = BuildLock::acquire(build_dir)?;
+    proj.load_vars(build_dir)?;
+    let changes = proj.changes_since_init(build_dir)?;
+    print_changes(build_dir, &changes);
+    let toolchains_changed = proj.detect_toolchains()?;
+    proj.generate(build_dir)?;
+    if !changes.is_empty() || toolchains_changed {
+        proj.save_state(build_dir)?;
+    }
+    Ok(())
+}
+
+/// Regenerate the build files if they are out of date, then run Ninja.
+/// Returns whether Ninja succeeded.
+fn build(proj: &mut Project, build_dir: &Path) -> Result<bool, failure::Error> {
+    // Not held while ninja runs, as ninja may run `pb generate`
+    if proj.has_state(build_dir) {
+        let _lock = BuildLock::acquire(build_dir)?;
+        let changes = proj.changes_since_init(build_dir)?;
+        if !changes.is_empty() {
+            print_changes(build_dir, &changes);
+            proj.load_vars(build_dir)?;
+            proj.detect_toolchains()?;
+            proj.generate(build_dir)?;
+            proj.save_state(build_dir)?;
+        }
+    }
+
+    let status = ExecCommand::new("ninja")
+        .arg("-C")
+        .arg(build_dir)
+        .spawn()
+        .context("Running ninja")?
+        .wait()
+        .context("Waiting for ninja child process")?;
+    Ok(status.success())
+}
+
+/// Say why a build directory is being regenerated.
+fn print_changes(build_dir: &Path, changes: &[String]) {
+    if changes.is_empty() {
+        return;
+    }
+
+    println!("Regenerating {} because:", build_dir.display());
+    for change in changes {
+        println!("  - {}", change);
+    }
+}
+
+/// The project file to use, and why.
+struct Located {
+    path: PathBuf,
+    origin: String,
+
+    /// The build directory the project file was found from, if any
+    build_dir: Option<PathBuf>,
+}
+
+/// Find the project file: the one given with -f, the one recorded in the
+/// build directory given with -d, or the first found from the current
+/// directory up.
+fn locate(config_file: Option<PathBuf>, build_dir: Option<&Path>) -> Result<Located, failure::Error> {
+    if let Some(path) = config_file {
+        return Ok(Located { path, origin: "-f option".to_string(), build_dir: None });
+    }
+
+    let recorded = match build_dir {
+        Some(dir) if State::exists(dir) => State::load(dir)?.project,
+        _ => None,
+    };
+    if let Some(path) = recorded {
+        return Ok(Located { path, origin: "recorded in build directory".to_string(), build_dir: None });
+    }
+
+    let cwd = std::env::current_dir().context("finding current directory")?;
+    let found = discover(&cwd)
+        .ok_or(err_msg("couldn't find modules.yaml, modules.toml or modules.json \
+                        in this directory or any parent"))?;
+    Ok(Located {
+        path: found.config_file,
+        origin: format!("found from {}", cwd.display()),
+        build_dir: found.build_dir,
+    })
+}
+
+/// A loaded project, with the user config and build directory to use.
+struct Opened {
+    proj: Project,
+    user: UserConfig,
+    located: Located,
+    build_dir: PathBuf,
+    build_dir_origin: String,
+}
+
+fn open(
+    dirs: &ProjectDirs,
+    config_file: Option<PathBuf>,
+    build_dir: Option<PathBuf>,
+) -> Result<Opened, failure::Error> {
+    let user = UserConfig::load(dirs)?;
+    let located = locate(config_file, build_dir.as_deref())?;
+    let proj = Project::load(&located.path)?;
+
+    let (build_dir, build_dir_origin) = match build_dir {
+        Some(dir) => (
+            std::fs::canonicalize(dir)
+                .context("finding build path")?
+                .to_path_buf(),
+            "-d option".to_string(),
+        ),
+        None => match (&located.build_dir, &user.build_dir) {
+            (Some(dir), _) => (dir.to_path_buf(), "current build directory".to_string()),
+            (None, Some(dir)) => (proj.root.join(dir), format!("user config {}", user.path.display())),
+            (None, None) => (proj.root.join("build"), "default".to_string()),
+        },
+    };
+
+    Ok(Opened { proj, user, located, build_dir, build_dir_origin })
+}
+
 fn main() -> Result<(), ExitFailure> {
     let proj_dirs = ProjectDirs::from("dev", "jsix", "bonnibel")
         .ok_or(err_msg("couldn't find home directory"))?;
@@ -68,47 +501,301 @@ fn main() -> Result<(), ExitFailure> {
     let opts = Bonnibel::from_args();
     opts.verbose.setup_env_logger("bonnibel")?;
 
-    let path = match opts.config_file {
-        Some(name) => name,
-        None => PathBuf::from("modules.yaml"),
-    };
+    let Bonnibel { config_file, build_dir, command, .. } = opts;
+    let open = || open(&proj_dirs, config_file.clone(), build_dir.clone());
 
-    let mut proj = Project::load(&path)?;
+    match command {
+        Command::Schema => {
+            println!("{}", serde_json::to_string_pretty(&Project::schema())?);
+        },
 
-    let build_dir = match opts.build_dir {
-        Some(dir) => std::fs::canonicalize(dir)
-            .context("finding build path")?
-            .to_path_buf(),
-        None => {
-            let mut dir = proj.root.to_path_buf();
-            dir.push("build");
-            dir
+        Command::New { dir, template } => {
+            let dir = dir.unwrap_or_else(|| PathBuf::from("."));
+            std::fs::create_dir_all(&dir).context("creating project directory")?;
+
+            for file in scaffold(&dir, &template)? {
+                println!("Created {}", file.display());
+            }
         },
-    };
 
-    match opts.command {
-        Command::Init { vars } => {
-            proj.parse_vars(vars)?;
-            proj.initialize(&build_dir)?;
-            proj.generate(&build_dir)?;
+        Command::Migrate { dry_run } => {
+            let path = locate(config_file.clone(), build_dir.as_deref())?.path;
+            let format = ConfigFormat::from_path(&path);
+            let contents = std::fs::read_to_string(&path).context("reading config file")?;
+
+            match migrate(&contents, format)? {
+                None => println!("{} is already at schema {}", path.display(), SCHEMA_VERSION),
+                Some((migrated, changes)) => {
+                    format.parse::<Project>(&migrated).context("validating migrated config file")?;
+
+                    println!("Migrating {} to schema {}:", path.display(), SCHEMA_VERSION);
+                    for change in changes {
+                        println!("  - {}", change);
+                    }
+
+                    if !dry_run {
+                        write_atomic(&path, migrated.as_bytes())?;
+                    }
+                },
+            }
         },
 
-        Command::Regenerate => {
+        Command::Module { command } => {
+            let path = locate(config_file.clone(), build_dir.as_deref())?.path;
+            match command {
+                ModuleCommand::Add { name, kind, target, output, source, depends } => {
+                    let mut fields = vec![("kind", scalar(&kind))];
+                    match (kind.as_str(), target) {
+                        ("exe", Some(target)) => fields.push(("target", scalar(&target))),
+                        ("exe", None) => Err(err_msg("executables need a --target"))?,
+                        ("lib", None) => {},
+                        ("lib", Some(_)) => Err(err_msg("only executables have a --target"))?,
+                        _ => Err(err_msg("--kind must be \"lib\" or \"exe\""))?,
+                    }
+
+                    let output = output.unwrap_or_else(|| match kind.as_str() {
+                        "exe" => format!("{}.elf", name),
+                        _ => format!("{}.a", name),
+                    });
+                    fields.push(("output", scalar(&output)));
+
+                    if !depends.is_empty() {
+                        fields.push(("depends", flow_list(&depends)));
+                    }
+                    fields.push(("source", flow_list(&source)));
+
+                    edit_config(&path, |e| e.add_module(&name, &fields))?;
+                    println!("Added module {}", name);
+                },
+
+                ModuleCommand::Remove { name } => {
+                    edit_config(&path, |e| e.remove_module(&name))?;
+                    println!("Removed module {}", name);
+                },
+
+                ModuleCommand::AddDep { module, dep } => {
+                    let mut added = false;
+                    edit_config(&path, |e| e.add_dependency(&module, &dep).map(|a| added = a))?;
+                    if added {
+                        println!("Module {} now depends on {}", module, dep);
+                    } else {
+                        println!("Module {} already depends on {}", module, dep);
+                    }
+                },
+            }
+        },
+
+        Command::Init { preset, vars_files, reprobe, matrix, matrix_dir, vars } => {
+            let Opened { mut proj, user, build_dir, .. } = open()?;
+            let init_opts = InitOptions { preset, vars_files, reprobe };
+
+            if matrix.is_empty() {
+                init(&mut proj, &user, &build_dir, &init_opts, vars)?;
+                register(&proj_dirs, &proj, &build_dir);
+            } else {
+                let axes = matrix.iter().map(|a| parse_axis(a)).collect::<Result<Vec<_>, _>>()?;
+                let mut dirs = Vec::new();
+                for combination in combinations(&axes) {
+                    let dir = matrix_build_dir(&build_dir, &proj.root, matrix_dir.as_deref(), &axes, &combination)?;
+                    if dirs.iter().any(|(d, _)| d == &dir) {
+                        return Err(format_err!(
+                            "--matrix-dir gives {} for more than one combination", dir.display()).into());
+                    }
+                    dirs.push((dir, combination));
+                }
+
+                for (dir, combination) in dirs {
+                    println!("Initializing {}", dir.display());
+                    let mut vars = vars.clone();
+                    vars.extend(combination.iter().map(|(name, value)| format!("{}={}", name, value)));
+
+                    let mut proj = Project::load(&proj.config_file)?;
+                    init(&mut proj, &user, &dir, &init_opts, vars)?;
+                    register(&proj_dirs, &proj, &dir);
+                }
+            }
+        },
+
+        Command::Regenerate { all: false } => {
+            let Opened { mut proj, build_dir, .. } = open()?;
+            regenerate(&mut proj, &build_dir)?;
+        },
+
+        Command::Regenerate { all: true } => {
+            let Opened { proj, .. } = open()?;
+            for dir in all_build_dirs(&proj_dirs, &proj)? {
+                println!("In {}:", dir.display());
+                regenerate(&mut Project::load(&proj.config_file)?, &dir)?;
+            }
+        },
+
+        Command::Config { show_origin, command } => {
+            let Opened { mut proj, user, located, build_dir, build_dir_origin } = open()?;
+            let user_origin = format!("user config {}", user.path.display());
+            let command = command.unwrap_or(ConfigCommand::List);
+
+            if show_origin && matches!(command, ConfigCommand::List) {
+                let (cache, cache_origin) = match &user.cache {
+                    Some(cache) => (cache.to_path_buf(), user_origin.as_str()),
+                    None => (proj_dirs.cache_dir().to_path_buf(), "default"),
+                };
+
+                println!("project = {} ({})", located.path.display(), located.origin);
+                println!("build_dir = {} ({})", build_dir.display(), build_dir_origin);
+                println!("cache = {} ({})", cache.display(), cache_origin);
+                for (prefix, mirror) in user.mirrors.iter() {
+                    println!("mirror {} = {} ({})", prefix, mirror, user_origin);
+                }
+
+                if !proj.has_state(&build_dir) {
+                    return Ok(());
+                }
+            }
+
+            if !proj.has_state(&build_dir) {
+                return Err(format_err!(
+                    "{} has not been initialized, run `pb init` first", build_dir.display()).into());
+            }
+
+            // Hold the lock from reading the state until it is saved
+            let _lock = match command {
+                ConfigCommand::Set { .. } | ConfigCommand::Unset { .. } =>
+                    Some(BuildLock::acquire(&build_dir)?),
+                _ => None,
+            };
             proj.load_vars(&build_dir)?;
-            proj.generate(&build_dir)?;
+
+            let describe = |name: &str| match proj.origin(name) {
+                Some(Origin::User) if show_origin => format!(" ({})", user_origin),
+                Some(origin) => format!(" ({})", origin),
+                None => String::new(),
+            };
+
+            match command {
+                ConfigCommand::Get { name } => {
+                    let value = proj.var(&name)?;
+                    if show_origin {
+                        println!("{}{}", display_value(value), describe(&name));
+                    } else {
+                        println!("{}", display_value(value));
+                    }
+                },
+
+                ConfigCommand::List => {
+                    let mut vars: Vec<_> = proj.vars().iter().collect();
+                    vars.sort_by(|a, b| a.0.cmp(b.0));
+                    for (name, value) in vars {
+                        println!("{} = {}{}", name, display_value(value), describe(name));
+                    }
+                },
+
+                ConfigCommand::Set { vars } => {
+                    proj.parse_vars(vars)?;
+                    reconfigure(&mut proj, &build_dir)?;
+                },
+
+                ConfigCommand::Unset { names } => {
+                    proj.unset_vars(&names)?;
+                    reconfigure(&mut proj, &build_dir)?;
+                },
+            }
         },
 
-        Command::Build => {
-            ExecCommand::new("ninja")
-                .arg("-C")
-                .arg(&build_dir)
-                .spawn()
-                .context("Running ninja")?
-                .wait()
-                .context("Waiting for ninja child process")?;
+        Command::Menuconfig => {
+            let Opened { mut proj, build_dir, .. } = open()?;
+            if !proj.has_state(&build_dir) {
+                return Err(format_err!(
+                    "{} has not been initialized, run `pb init` first", build_dir.display()).into());
+            }
+
+            // Hold the lock for the whole session, so no other pb changes the
+            // vars being edited
+            let _lock = BuildLock::acquire(&build_dir)?;
+            proj.load_vars(&build_dir)?;
+
+            if menuconfig(&mut proj)? {
+                reconfigure(&mut proj, &build_dir)?;
+            }
+        },
+
+        Command::Vars => {
+            let Opened { mut proj, build_dir, .. } = open()?;
+            if proj.has_state(&build_dir) {
+                proj.load_vars(&build_dir)?;
+            }
+
+            if proj.variables.is_empty() {
+                println!("{} declares no variables", proj.name);
+            }
+
+            for (name, var) in proj.variables.iter() {
+                let mut line = match &var.env {
+                    Some(env) => format!("{} ({}, from ${})", name, var.kind, env),
+                    None => format!("{} ({})", name, var.kind),
+                };
+                if let Some(value) = proj.vars().get(name) {
+                    line.push_str(&format!(" = {}", display_value(value)));
+                }
+                if !var.choices.is_empty() {
+                    line.push_str(&format!(" [{}]", var.choices.join(", ")));
+                }
+                println!("{}", line);
+
+                if let Some(help) = &var.help {
+                    println!("    {}", help);
+                }
+            }
+
+            if !proj.presets.is_empty() {
+                println!();
+                println!("Presets:");
+            }
+
+            for (name, preset) in proj.presets.iter() {
+                let current = if proj.preset.as_ref() == Some(name) { " (current)" } else { "" };
+                match &preset.inherits {
+                    Some(base) => println!("{} (inherits {}){}", name, base, current),
+                    None => println!("{}{}", name, current),
+                }
+
+                if let Some(help) = &preset.help {
+                    println!("    {}", help);
+                }
+            }
+        },
+
+        Command::Templates => {
+            let Opened { mut proj, build_dir, .. } = open()?;
+            if proj.has_state(&build_dir) {
+                proj.load_vars(&build_dir)?;
+            } else {
+                proj.resolve()?;
+            }
+
+            for (name, choice) in proj.template_choices()? {
+                println!("{}: {}", name, choice);
+            }
+        },
+
+        Command::Build { all: false } => {
+            let Opened { mut proj, build_dir, .. } = open()?;
+            if !build(&mut proj, &build_dir)? {
+                return Err(format_err!("build failed in {}", build_dir.display()).into());
+            }
+        },
+
+        Command::Build { all: true } => {
+            let Opened { proj, .. } = open()?;
+            for dir in all_build_dirs(&proj_dirs, &proj)? {
+                println!("In {}:", dir.display());
+                if !build(&mut Project::load(&proj.config_file)?, &dir)? {
+                    return Err(format_err!("build failed in {}", dir.display()).into());
+                }
+            }
         },
 
         Command::Clean => {
+            let Opened { build_dir, .. } = open()?;
             ExecCommand::new("ninja")
                 .arg("-C")
                 .arg(&build_dir)
@@ -121,12 +808,15 @@ fn main() -> Result<(), ExitFailure> {
         },
 
         Command::Sync { cache } => {
-            let cache = match cache {
-                Some(path) => path,
-                None => proj_dirs.cache_dir().to_path_buf(),
+            let Opened { mut proj, user, .. } = open()?;
+            let cache = match (cache, &user.cache) {
+                (Some(path), _) => path,
+                (None, Some(path)) => path.to_path_buf(),
+                (None, None) => proj_dirs.cache_dir().to_path_buf(),
             };
 
             for o in proj.overlays.iter_mut() {
+                o.use_mirrors(&user.mirrors);
                 o.compute_for_cache(&cache)?;
 
                 if !o.is_cached() {
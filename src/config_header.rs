@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use failure::ResultExt;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use super::lock::write_atomic;
+use super::variable::scalar_string;
+use super::{VarType, Variable};
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+fn default_path() -> PathBuf {
+    PathBuf::from("config.h")
+}
+
+fn default_prefix() -> String {
+    "CONFIG_".to_string()
+}
+
+/// Options for the configuration header generated from the project's
+/// variables, set in the project's `config_header` section.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ConfigHeader {
+    /// The C header to write, relative to the build directory
+    #[serde(default = "default_path")]
+    pub path: PathBuf,
+
+    /// The prefix for each macro name
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+
+    /// A file of `--cfg` arguments for rustc to also write, relative to the
+    /// build directory
+    #[serde(default)]
+    pub rust_cfg: Option<PathBuf>,
+}
+
+/// A name made safe to use as part of a C identifier or Rust cfg name.
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// A name made safe to use as part of a C macro name.
+fn macro_name(name: &str) -> String {
+    identifier(name).to_ascii_uppercase()
+}
+
+/// A value as a C or Rust string literal.
+fn literal(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// The name, declaration if any and current value of each variable to write.
+pub type ConfigVars<'a> = BTreeMap<&'a str, (Option<&'a Variable>, Option<&'a Value>)>;
+
+impl ConfigHeader {
+    /// Render the C header. Bools become defined or not, ints become
+    /// numbers and everything else a string. Enums, and lists with declared
+    /// choices, also get a macro for each value they hold.
+    pub fn header(&self, project: &str, vars: &ConfigVars) -> String {
+        let mut lines = vec![
+            format!("/* Generated by bonnibel for {}, do not edit */", project),
+            "#pragma once".to_string(),
+            String::new(),
+        ];
+
+        for (name, (var, value)) in vars {
+            let m = format!("{}{}", self.prefix, macro_name(name));
+            let kind = var.map(|v| v.kind).unwrap_or_default();
+            let choices = var.map(|v| !v.choices.is_empty()).unwrap_or(false);
+
+            match (kind, value) {
+                (_, None) | (_, Some(Value::Null)) | (_, Some(Value::Bool(false))) =>
+                    lines.push(format!("/* {} is not set */", m)),
+                (_, Some(Value::Bool(true))) => lines.push(format!("#define {} 1", m)),
+                (_, Some(Value::Number(n))) => lines.push(format!("#define {} {}", m, n)),
+                (_, Some(Value::Sequence(items))) => {
+                    let items: Vec<String> = items.iter().filter_map(scalar_string).collect();
+                    lines.push(format!("#define {} {}", m, literal(&items.join(" "))));
+                    for item in items.iter().filter(|_| choices) {
+                        lines.push(format!("#define {}_{} 1", m, macro_name(item)));
+                    }
+                },
+                (_, Some(Value::Mapping(_))) => lines.push(format!("/* {} is a map */", m)),
+                (VarType::Enum, Some(Value::String(s))) => {
+                    lines.push(format!("#define {} {}", m, literal(s)));
+                    lines.push(format!("#define {}_{} 1", m, macro_name(s)));
+                },
+                (_, Some(Value::String(s))) => lines.push(format!("#define {} {}", m, literal(s))),
+            }
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    /// Render the rustc `--cfg` arguments. True bools become a bare cfg,
+    /// lists a cfg for each item, and other values a `name="value"` cfg.
+    pub fn rust_cfg(&self, vars: &ConfigVars) -> String {
+        let mut lines = Vec::new();
+        for (name, (_, value)) in vars {
+            let name = identifier(name);
+            match value {
+                Some(Value::Bool(true)) => lines.push(format!("--cfg {}", name)),
+                Some(Value::Sequence(items)) => {
+                    for item in items.iter().filter_map(scalar_string) {
+                        lines.push(format!("--cfg {}={}", name, literal(&item)));
+                    }
+                },
+                Some(value @ Value::String(_)) | Some(value @ Value::Number(_)) => {
+                    let value = scalar_string(value).unwrap();
+                    lines.push(format!("--cfg {}={}", name, literal(&value)));
+                },
+                _ => {},
+            }
+        }
+
+        lines.into_iter().map(|l| l + "\n").collect()
+    }
+}
+
+/// Write `contents` to `path` only if it would change the file, so that
+/// anything depending on it is only rebuilt when needed. Returns whether
+/// the file was written.
+pub fn write_if_changed(path: &Path, contents: &str) -> Result<bool> {
+    if std::fs::read_to_string(path).ok().as_deref() == Some(contents) {
+        return Ok(false);
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("creating config header directory")?;
+    }
+    write_atomic(path, contents.as_bytes())?;
+    Ok(true)
+}
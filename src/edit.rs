@@ -0,0 +1,432 @@
+//! In-place editing of YAML project files. Edits work on the text of the
+//! file rather than on a parsed document so that comments, key order and
+//! formatting are all left alone.
+
+use std::fmt;
+use std::path::Path;
+
+use failure::{format_err, ResultExt};
+use serde_yaml::Value;
+use tempfile::Builder;
+
+use super::module::split_reference;
+use super::{ConfigFormat, Project};
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+const DEFAULT_INDENT: usize = 2;
+
+/// Render a string as a YAML scalar, quoting it only if needed.
+pub fn scalar(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars().all(|c| c.is_alphanumeric() || "_./+-".contains(c))
+        && !s.starts_with('-')
+        && !matches!(s, "true" | "false" | "yes" | "no" | "on" | "off" | "null" | "~");
+
+    if plain {
+        s.to_string()
+    } else {
+        serde_json::to_string(s).unwrap()
+    }
+}
+
+/// Render a list of strings as a YAML flow sequence.
+pub fn flow_list(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|i| scalar(i)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Lines that don't affect the structure of the document.
+fn is_filler(line: &str) -> bool {
+    let t = line.trim();
+    t.is_empty() || t.starts_with('#')
+}
+
+/// If `line` is a mapping key with no inline value, return the key.
+fn block_key(line: &str) -> Option<&str> {
+    let t = line.trim();
+    let t = match t.find(" #") {
+        Some(i) => t[..i].trim_end(),
+        None => t,
+    };
+    if t.ends_with(':') && !t.starts_with('-') {
+        Some(t[..t.len()-1].trim_matches(|c| c == '"' || c == '\''))
+    } else {
+        None
+    }
+}
+
+/// If `line` is a mapping key, return the key and its inline value.
+fn key_value(line: &str) -> Option<(&str, &str)> {
+    let t = line.trim();
+    if t.starts_with('-') || t.starts_with('#') {
+        return None;
+    }
+    let i = t.find(':')?;
+    Some((t[..i].trim_matches(|c| c == '"' || c == '\''), t[i+1..].trim()))
+}
+
+struct Entry {
+    name: String,
+    line: usize,
+    end: usize,
+}
+
+/// A YAML project file being edited.
+pub struct YamlEditor {
+    lines: Vec<String>,
+    trailing_newline: bool,
+}
+
+impl YamlEditor {
+    pub fn new(text: &str) -> YamlEditor {
+        YamlEditor {
+            lines: text.lines().map(|l| l.to_string()).collect(),
+            trailing_newline: text.ends_with('\n'),
+        }
+    }
+
+    /// The index just past the last line of the block started at `start`,
+    /// not counting any trailing blank lines or comments.
+    fn block_end(&self, start: usize) -> usize {
+        let indent = indent_of(&self.lines[start]);
+        let mut end = start + 1;
+        for (i, line) in self.lines.iter().enumerate().skip(start + 1) {
+            if is_filler(line) {
+                continue;
+            }
+            if indent_of(line) <= indent {
+                break;
+            }
+            end = i + 1;
+        }
+        end
+    }
+
+    /// The index just past the value of the key on line `start`, including
+    /// any block sequence items written at the key's own indentation.
+    fn value_end(&self, start: usize) -> usize {
+        let indent = indent_of(&self.lines[start]);
+        let mut end = start + 1;
+        for (i, line) in self.lines.iter().enumerate().skip(start + 1) {
+            if is_filler(line) {
+                continue;
+            }
+            let this_indent = indent_of(line);
+            if this_indent < indent || (this_indent == indent && !line.trim_start().starts_with('-')) {
+                break;
+            }
+            end = i + 1;
+        }
+        end
+    }
+
+    fn modules_line(&self) -> Result<usize> {
+        self.lines
+            .iter()
+            .position(|l| indent_of(l) == 0 && block_key(l) == Some("modules"))
+            .ok_or_else(|| format_err!("couldn't find a block-style `modules:` section to edit"))
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let start = self.modules_line()?;
+        let end = self.block_end(start);
+
+        let mut entries = Vec::new();
+        let mut indent = None;
+        for i in start + 1 .. end {
+            let line = &self.lines[i];
+            if is_filler(line) {
+                continue;
+            }
+
+            let this_indent = indent_of(line);
+            if *indent.get_or_insert(this_indent) != this_indent {
+                continue;
+            }
+
+            let name = block_key(line)
+                .ok_or_else(|| format_err!("couldn't understand module entry on line {}", i + 1))?;
+            entries.push(Entry { name: name.to_string(), line: i, end: self.block_end(i) });
+        }
+
+        Ok(entries)
+    }
+
+    fn entry(&self, name: &str) -> Result<Entry> {
+        self.entries()?
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or_else(|| format_err!("no module named '{}'", name))
+    }
+
+    /// The indentation of module names, and of the keys within a module.
+    fn indents(&self) -> Result<(usize, usize)> {
+        let entries = self.entries()?;
+        let first = match entries.first() {
+            Some(e) => e,
+            None => return Ok((DEFAULT_INDENT, DEFAULT_INDENT * 2)),
+        };
+
+        let outer = indent_of(&self.lines[first.line]);
+        let inner = self.lines[first.line + 1 .. first.end]
+            .iter()
+            .find(|l| !is_filler(l))
+            .map(|l| indent_of(l))
+            .unwrap_or(outer + DEFAULT_INDENT);
+
+        Ok((outer, inner))
+    }
+
+    /// The line of `key` directly within a module, if it has one.
+    fn module_key(&self, entry: &Entry, key: &str) -> Option<usize> {
+        let indent = self.lines[entry.line + 1 .. entry.end]
+            .iter()
+            .find(|l| !is_filler(l))
+            .map(|l| indent_of(l))?;
+
+        (entry.line + 1 .. entry.end).find(|&i| {
+            let line = &self.lines[i];
+            !is_filler(line) && indent_of(line) == indent && key_value(line).map(|(k, _)| k) == Some(key)
+        })
+    }
+
+    /// Add a module at the end of the modules section. Each field is a key
+    /// and an already-rendered YAML value.
+    pub fn add_module(&mut self, name: &str, fields: &[(&str, String)]) -> Result<()> {
+        let entries = self.entries()?;
+        if entries.iter().any(|e| e.name == name) {
+            return Err(format_err!("module '{}' already exists", name));
+        }
+
+        let (outer, inner) = self.indents()?;
+        let at = match entries.last() {
+            Some(e) => e.end,
+            None => self.modules_line()? + 1,
+        };
+
+        // Keep modules separated by blank lines if they already are
+        let spaced = entries.len() > 1 && self.lines[entries[1].line - 1].trim().is_empty();
+
+        let mut new_lines = Vec::new();
+        if spaced {
+            new_lines.push(String::new());
+        }
+        new_lines.push(format!("{}{}:", " ".repeat(outer), scalar(name)));
+        for (key, value) in fields {
+            new_lines.push(format!("{}{}: {}", " ".repeat(inner), key, value));
+        }
+
+        self.lines.splice(at .. at, new_lines);
+        Ok(())
+    }
+
+    /// Remove a module, everything nested under it, and the comments
+    /// directly above it.
+    pub fn remove_module(&mut self, name: &str) -> Result<()> {
+        let entry = self.entry(name)?;
+        let indent = indent_of(&self.lines[entry.line]);
+
+        let mut start = entry.line;
+        while start > 0 {
+            let line = &self.lines[start - 1];
+            if !line.trim_start().starts_with('#') || indent_of(line) != indent {
+                break;
+            }
+            start -= 1;
+        }
+        self.lines.drain(start .. entry.end);
+
+        // Don't leave behind the blank line that separated it from the others
+        let blank = |lines: &[String], i: usize| lines.get(i).map(|l| l.trim().is_empty());
+        if start > 0 && blank(&self.lines, start - 1) == Some(true) && blank(&self.lines, start) != Some(false) {
+            self.lines.remove(start - 1);
+        } else if blank(&self.lines, start) == Some(true) && start == self.modules_line()? + 1 {
+            self.lines.remove(start);
+        }
+        Ok(())
+    }
+
+    /// Rename a key directly within a module, keeping its value.
+    pub fn rename_module_key(&mut self, module: &str, from: &str, to: &str) -> Result<()> {
+        let entry = self.entry(module)?;
+        let i = self.module_key(&entry, from)
+            .ok_or_else(|| format_err!("module '{}' has no `{}` key", module, from))?;
+
+        let line = &mut self.lines[i];
+        let at = line.find(from).unwrap();
+        line.replace_range(at .. at + from.len(), to);
+        Ok(())
+    }
+
+    /// Remove a key directly within a module, along with its value.
+    pub fn remove_module_key(&mut self, module: &str, key: &str) -> Result<()> {
+        let entry = self.entry(module)?;
+        let i = self.module_key(&entry, key)
+            .ok_or_else(|| format_err!("module '{}' has no `{}` key", module, key))?;
+
+        let end = self.value_end(i);
+        self.lines.drain(i .. end);
+        Ok(())
+    }
+
+    /// Set the top-level `key` to a mapping of already-rendered YAML values,
+    /// replacing it where it is, or adding it after any comments at the
+    /// top of the file.
+    pub fn set_top_level(&mut self, key: &str, fields: &[(&str, String)]) -> Result<()> {
+        let indent = match self.indents() {
+            Ok((outer, _)) if outer > 0 => outer,
+            _ => DEFAULT_INDENT,
+        };
+
+        let mut new_lines = vec![format!("{}:", key)];
+        for (field, value) in fields {
+            new_lines.push(format!("{}{}: {}", " ".repeat(indent), field, value));
+        }
+
+        let existing = self.lines
+            .iter()
+            .position(|l| indent_of(l) == 0 && key_value(l).map(|(k, _)| k) == Some(key));
+
+        match existing {
+            Some(i) => {
+                let end = self.value_end(i);
+                self.lines.splice(i .. end, new_lines);
+            },
+            None => {
+                let at = self.lines.iter().take_while(|l| l.starts_with('#')).count();
+                if self.lines.get(at).map(|l| !l.trim().is_empty()).unwrap_or(false) {
+                    new_lines.push(String::new());
+                }
+                self.lines.splice(at .. at, new_lines);
+            },
+        }
+        Ok(())
+    }
+
+    /// The dependencies `module` lists, under `depends` or the old `deps`.
+    fn dependencies(&self, module: &str) -> Result<Vec<String>> {
+        let doc: Value = serde_yaml::from_str(&self.to_string()).context("parsing config file")?;
+        let fields = &doc["modules"][module];
+        Ok(["depends", "deps"]
+            .iter()
+            .filter_map(|key| fields[*key].as_sequence())
+            .flatten()
+            .filter_map(|d| d.as_str().map(String::from))
+            .collect())
+    }
+
+    /// Add `dep` to the `depends` list of `module`, whether it's written in
+    /// flow or block style, creating the list if needed. Returns whether it
+    /// was added, which it isn't if `module` already depends on `dep`.
+    pub fn add_dependency(&mut self, module: &str, dep: &str) -> Result<bool> {
+        if split_reference(dep).0 == module {
+            return Err(format_err!("module '{}' can't depend on itself", module));
+        }
+
+        let entry = self.entry(module)?;
+        if self.dependencies(module)?.iter().any(|d| d == dep) {
+            return Ok(false);
+        }
+        let (_, inner) = self.indents()?;
+
+        let key_line = (entry.line + 1 .. entry.end).find(|&i| {
+            let line = &self.lines[i];
+            !is_filler(line)
+                && indent_of(line) == inner
+                && matches!(key_value(line), Some(("depends", _)) | Some(("deps", _)))
+        });
+
+        let key_line = match key_line {
+            Some(i) => i,
+            None => {
+                let line = format!("{}depends: [{}]", " ".repeat(inner), scalar(dep));
+                self.lines.insert(entry.end, line);
+                return Ok(true);
+            },
+        };
+
+        let (_, value) = key_value(&self.lines[key_line]).unwrap();
+        if value.is_empty() || value.starts_with('#') {
+            // Block sequence: add an item after the last one. Items may be
+            // indented further than the key, or at the same level.
+            let mut end = key_line + 1;
+            let mut item_indent = inner + DEFAULT_INDENT;
+            for i in key_line + 1 .. entry.end {
+                let line = &self.lines[i];
+                if is_filler(line) {
+                    continue;
+                }
+                let this_indent = indent_of(line);
+                if this_indent > inner || line.trim_start().starts_with('-') {
+                    if line.trim_start().starts_with('-') {
+                        item_indent = this_indent;
+                    }
+                    end = i + 1;
+                } else {
+                    break;
+                }
+            }
+            self.lines.insert(end, format!("{}- {}", " ".repeat(item_indent), scalar(dep)));
+        } else if value.starts_with('[') {
+            let line = &mut self.lines[key_line];
+            let close = line.rfind(']')
+                .ok_or_else(|| format_err!("couldn't find the end of the depends list on line {}", key_line + 1))?;
+            let empty = line[..close].trim_end().ends_with('[');
+            let insert = if empty { scalar(dep) } else { format!(", {}", scalar(dep)) };
+            line.insert_str(close, &insert);
+        } else {
+            return Err(format_err!("couldn't understand the depends list on line {}", key_line + 1));
+        }
+
+        Ok(true)
+    }
+}
+
+impl fmt::Display for YamlEditor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.lines.join("\n"))?;
+        if self.trailing_newline {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Edit a project file in place, validating the result with the normal
+/// loader before replacing the original.
+pub fn edit_config<F>(path: &Path, edit: F) -> Result<()>
+where
+    F: FnOnce(&mut YamlEditor) -> Result<()>,
+{
+    if ConfigFormat::from_path(path) != ConfigFormat::Yaml {
+        return Err(format_err!("only YAML project files can be edited"));
+    }
+
+    let contents = std::fs::read_to_string(path).context("reading config file")?;
+    let mut editor = YamlEditor::new(&contents);
+    edit(&mut editor)?;
+
+    // Write next to the original so that relative paths resolve the same
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let temp = Builder::new()
+        .prefix(".modules")
+        .suffix(".yaml")
+        .tempfile_in(dir)
+        .context("creating temporary config file")?;
+    std::fs::write(temp.path(), editor.to_string()).context("writing temporary config file")?;
+    std::fs::set_permissions(temp.path(), std::fs::metadata(path)?.permissions())?;
+
+    // Which modules are enabled depends on the vars of each build
+    // directory, so only check what holds for all of them
+    Project::load(temp.path())
+        .and_then(|p| p.check_references())
+        .context("validating edited config file")?;
+
+    temp.persist(path).context("replacing config file")?;
+    Ok(())
+}
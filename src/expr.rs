@@ -0,0 +1,294 @@
+//! A tiny boolean expression language for conditional module fields.
+//!
+//! An expression is built from variable names, comparisons and the usual
+//! boolean operators:
+//!
+//!   debug_tools
+//!   !release && arch == x86_64
+//!   (arch == "x86_64" || arch == aarch64) && build != release
+//!
+//! A bare variable name is true if the variable is set to anything other
+//! than false, zero, an empty list or map, or one of the strings "", "0",
+//! "false", "no" or "off". In a comparison the left side is always a
+//! variable name and the right side a literal value, optionally quoted. A
+//! list variable equals a value if it contains it.
+
+use std::collections::HashMap;
+
+use failure::format_err;
+use serde_yaml::Value;
+
+use super::variable::scalar_string;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Not,
+    And,
+    Or,
+    Eq,
+    Ne,
+    Open,
+    Close,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => { chars.next(); },
+            '(' => { chars.next(); tokens.push(Token::Open); },
+            ')' => { chars.next(); tokens.push(Token::Close); },
+            '!' | '=' | '&' | '|' => {
+                chars.next();
+                let next = chars.peek().copied();
+                let token = match (c, next) {
+                    ('!', Some('=')) => Token::Ne,
+                    ('!', _) => Token::Not,
+                    ('=', Some('=')) => Token::Eq,
+                    ('&', Some('&')) => Token::And,
+                    ('|', Some('|')) => Token::Or,
+                    _ => return Err(format_err!("unexpected '{}' in expression '{}'", c, expr)),
+                };
+                if token != Token::Not {
+                    chars.next();
+                }
+                tokens.push(token);
+            },
+            '"' | '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => s.push(ch),
+                        None => return Err(format_err!("unterminated string in expression '{}'", expr)),
+                    }
+                }
+                tokens.push(Token::Quoted(s));
+            },
+            c if c.is_alphanumeric() || "_.-+/".contains(c) => {
+                let mut s = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_alphanumeric() || "_.-+/".contains(ch) {
+                        s.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Word(s));
+            },
+            _ => return Err(format_err!("unexpected '{}' in expression '{}'", c, expr)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Whether a variable value counts as true.
+pub fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !matches!(s.to_lowercase().as_str(), "" | "0" | "false" | "no" | "off"),
+        Value::Sequence(items) => !items.is_empty(),
+        Value::Mapping(map) => !map.is_empty(),
+    }
+}
+
+/// Whether a variable value matches a literal from an expression.
+fn matches(value: &Value, literal: &str) -> bool {
+    match value {
+        Value::Sequence(items) => items.iter().any(|i| scalar_string(i).as_deref() == Some(literal)),
+        _ => scalar_string(value).as_deref() == Some(literal),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    expr: &'a str,
+    vars: &'a HashMap<String, Value>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn error(&self, what: &str) -> failure::Error {
+        format_err!("{} in expression '{}'", what, self.expr)
+    }
+
+    fn or(&mut self) -> Result<bool> {
+        let mut value = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            value = self.and()? || value;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<bool> {
+        let mut value = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            value = self.unary()? && value;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<bool> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            Ok(!self.unary()?)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<bool> {
+        match self.next() {
+            Some(Token::Open) => {
+                let value = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err(self.error("expected ')'")),
+                }
+            },
+            Some(Token::Word(name)) => {
+                let value = self.vars.get(&name).unwrap_or(&Value::Null);
+                let negate = match self.peek() {
+                    Some(Token::Eq) => false,
+                    Some(Token::Ne) => true,
+                    _ => return Ok(truthy(value)),
+                };
+                self.next();
+
+                let literal = match self.next() {
+                    Some(Token::Word(s)) | Some(Token::Quoted(s)) => s,
+                    _ => return Err(self.error("expected a value to compare against")),
+                };
+                Ok(matches(value, &literal) != negate)
+            },
+            Some(_) => Err(self.error("unexpected operator")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+}
+
+/// The variable names an expression uses, leaving out the values they are
+/// compared against.
+pub fn variables(expr: &str) -> Result<Vec<String>> {
+    let tokens = tokenize(expr)?;
+    let mut names = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let compared = i > 0 && matches!(tokens[i - 1], Token::Eq | Token::Ne);
+        if let (Token::Word(name), false) = (token, compared) {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Evaluate a boolean expression against the given variables.
+pub fn evaluate(expr: &str, vars: &HashMap<String, Value>) -> Result<bool> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        expr,
+        vars,
+    };
+
+    let value = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("unexpected trailing input"));
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, Value> {
+        let mut vars = HashMap::new();
+        vars.insert("debug".to_string(), Value::Bool(true));
+        vars.insert("release".to_string(), Value::Bool(false));
+        vars.insert("arch".to_string(), Value::String("x86_64".to_string()));
+        vars.insert("features".to_string(), serde_yaml::from_str("[smp, acpi]").unwrap());
+        vars
+    }
+
+    fn eval(expr: &str) -> bool {
+        evaluate(expr, &vars()).unwrap()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(eval("debug || release && release"));
+        assert!(!eval("(debug || release) && release"));
+        assert!(eval("release && release || debug"));
+    }
+
+    #[test]
+    fn not() {
+        assert!(eval("!release"));
+        assert!(!eval("!debug"));
+        assert!(eval("!!debug"));
+        assert!(eval("!release && debug"));
+        assert!(!eval("!(release || debug)"));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(eval("arch == x86_64"));
+        assert!(eval("arch == \"x86_64\""));
+        assert!(eval("arch != 'aarch64'"));
+        assert!(!eval("arch != x86_64"));
+        assert!(eval("features == acpi"));
+        assert!(!eval("features == pci"));
+        assert!(eval("debug == true"));
+    }
+
+    #[test]
+    fn parentheses() {
+        assert!(eval("(arch == aarch64 || arch == x86_64) && debug"));
+        assert!(eval("((debug))"));
+    }
+
+    #[test]
+    fn unknown_vars_are_false() {
+        assert!(!eval("missing"));
+        assert!(eval("!missing"));
+        assert!(eval("missing != x86_64"));
+    }
+
+    #[test]
+    fn variable_names() {
+        assert_eq!(variables("!debug && (arch == x86_64 || features != 'smp')").unwrap(),
+            ["debug", "arch", "features"]);
+        assert!(variables("debug &&&").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        for expr in &["", "debug &&", "(debug", "debug)", "debug = x86_64", "arch ==", "'open", "debug & release", "$"] {
+            assert!(evaluate(expr, &vars()).is_err(), "'{}' should not parse", expr);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Project file names looked for when none is given, in order of preference.
+pub const CONFIG_FILE_NAMES: &[&str] = &["modules.yaml", "modules.yml", "modules.toml", "modules.json"];
+
+/// The formats a project file may be written in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Choose a format from a file's extension. Anything unrecognized is
+    /// read as YAML, which was the only format before.
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            Some("toml") => ConfigFormat::Toml,
+            Some("json") => ConfigFormat::Json,
+            _ => {
+                warn!("Unknown project file format for {:?}, reading it as YAML", path);
+                ConfigFormat::Yaml
+            },
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, contents: &str) -> Result<T> {
+        Ok(match self {
+            ConfigFormat::Yaml => serde_yaml::from_str(contents)?,
+            ConfigFormat::Toml => toml::from_str(contents)?,
+            ConfigFormat::Json => serde_json::from_str(contents)?,
+        })
+    }
+
+    pub fn write<T: Serialize>(self, value: &T) -> Result<String> {
+        Ok(match self {
+            ConfigFormat::Yaml => serde_yaml::to_string(value)? + "\n",
+            ConfigFormat::Toml => toml::to_string_pretty(&toml::Value::try_from(value)?)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(value)? + "\n",
+        })
+    }
+}
+
+/// Find the project file in a directory.
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    let mut found = CONFIG_FILE_NAMES.iter().map(|n| dir.join(n)).filter(|p| p.is_file());
+    let first = found.next();
+
+    if let Some(first) = &first {
+        for other in found {
+            warn!("Ignoring {:?} in favor of {:?}", other, first);
+        }
+    }
+
+    first
+}
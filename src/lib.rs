@@ -1,25 +1,78 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::error::Error as StdError;
-use std::io::Write;
 use std::path::{Path, PathBuf};
 
 use failure::{err_msg, Fail, format_err, ResultExt};
 use git2::{DescribeFormatOptions, DescribeOptions, Repository};
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use semver::{Identifier, Version};
+use schemars::JsonSchema;
 use serde::Deserialize;
+use serde_yaml::Value;
 use tera::{Context, Tera};
 
+mod expr;
+
+mod config_header;
+use config_header::{ConfigHeader, ConfigVars};
+
+mod edit;
+pub use edit::{edit_config, flow_list, scalar, YamlEditor};
+
+mod format;
+pub use format::{find_config, ConfigFormat};
+
+mod lock;
+pub use lock::{write_atomic, BuildLock};
+
+mod menuconfig;
+pub use menuconfig::menuconfig;
+
+mod migrate;
+pub use migrate::{migrate, Header, SCHEMA_VERSION};
+
 mod module;
-use module::{Module, ModuleKind};
+use module::{Module, ModuleKind, Visibility};
 
 mod overlay;
 use overlay::Overlay;
 
-type Result<T> = std::result::Result<T, failure::Error>;
+mod preset;
+use preset::Preset;
+
+mod probe;
+use probe::{Probe, ProbeRecord};
+
+mod registry;
+pub use registry::{known_build_dirs, register_build_dir};
 
-const VAR_FILE_NAME: &'static str = ".bonnibel_vars";
+mod scaffold;
+pub use scaffold::{scaffold, SCAFFOLDS};
+
+mod state;
+pub use state::{discover, Discovered, State};
+use state::content_hash;
+
+mod subproject;
+use subproject::Subproject;
+
+mod template;
+pub use template::TemplateChoice;
+use template::{find_template, TargetOptions};
+
+mod toolchain;
+use toolchain::{DetectedToolchain, Toolchain};
+
+mod user_config;
+pub use user_config::UserConfig;
+
+mod vars_file;
+
+mod variable;
+pub use variable::{display as display_value, Origin, VarType, Variable};
+
+type Result<T> = std::result::Result<T, failure::Error>;
 
 fn tera_failure(e: tera::Error) -> failure::Error {
     let mut base = failure::Context::new(e.to_string());
@@ -32,9 +85,17 @@ fn tera_failure(e: tera::Error) -> failure::Error {
     base.into()
 }
 
-#[derive(Debug, Deserialize)]
+/// A Bonnibel project file.
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Project {
+    /// The project file format version and required Bonnibel version
+    #[serde(default)]
+    pub bonnibel: Option<Header>,
+
+    /// The project name
     pub name: String,
+
+    /// The directory holding the project's templates
     templates: PathBuf,
 
     #[serde(skip)]
@@ -43,28 +104,97 @@ pub struct Project {
     #[serde(skip)]
     pub config_file: PathBuf,
 
+    /// A hash of this project file and each subproject's, keyed by path
+    #[serde(skip)]
+    config_hashes: BTreeMap<PathBuf, String>,
+
+    /// A hash of each vars file read at init time, keyed by path
+    #[serde(skip)]
+    vars_file_hashes: BTreeMap<PathBuf, String>,
+
+    /// Default values for variables passed to templates
     #[serde(default)]
-    vars: HashMap<String, String>,
+    #[schemars(with = "HashMap<String, serde_json::Value>")]
+    vars: HashMap<String, Value>,
 
+    /// Declarations of the variables this project understands, keyed by
+    /// name. If any are declared, only declared variables may be set.
+    #[serde(default)]
+    pub variables: BTreeMap<String, Variable>,
+
+    /// External packages extracted into the source tree by `pb sync`
     #[serde(default)]
     pub overlays: Vec<Overlay>,
 
+    /// Other Bonnibel projects whose modules are loaded into a namespace
+    #[serde(default)]
+    pub subprojects: Vec<Subproject>,
+
+    /// The modules to build, keyed by name
     pub modules: HashMap<String, Module>,
 
+    /// Named sets of variables chosen with `pb init --preset`
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+
+    /// Commands run at init time whose results are used like variables,
+    /// keyed by name
+    #[serde(default)]
+    pub probes: BTreeMap<String, Probe>,
+
+    /// Generate a C header, and optionally a rustc cfg file, from the
+    /// project's variables
+    #[serde(default)]
+    pub config_header: Option<ConfigHeader>,
+
+    /// Tools to build targets with, keyed by name
+    #[serde(default)]
+    pub toolchains: BTreeMap<String, Toolchain>,
+
+    /// Per-target options, keyed by target name
+    #[serde(default, rename = "targets")]
+    target_options: HashMap<String, TargetOptions>,
+
     #[serde(skip)]
     targets: HashMap<String, HashSet<String>>,
+
+    /// Where each variable's current value came from
+    #[serde(skip)]
+    origins: HashMap<String, Origin>,
+
+    /// The preset chosen at init time
+    #[serde(skip)]
+    pub preset: Option<String>,
+
+    /// The results of the probes run at init time
+    #[serde(skip)]
+    probe_results: BTreeMap<String, ProbeRecord>,
+
+    /// The toolchains found at init time
+    #[serde(skip)]
+    toolchain_results: BTreeMap<String, DetectedToolchain>,
 }
 
 impl Project {
     pub fn load(filename: &Path) -> Result<Project> {
+        Project::load_within(filename, &mut Vec::new())
+    }
+
+    /// Load a project file as a subproject of `loading`, the chain of
+    /// project files already being loaded.
+    fn load_within(filename: &Path, loading: &mut Vec<PathBuf>) -> Result<Project> {
         let config = std::fs::read_to_string(filename).context("reading config file")?;
-        let mut proj: Project = serde_yaml::from_str(&config).context("parsing config file")?;
+        let format = ConfigFormat::from_path(filename);
+        migrate::check_header(&config, format)?;
+
+        let mut proj: Project = format.parse(&config).context("parsing config file")?;
 
         proj.config_file = std::fs::canonicalize(filename)
             .context("finding project path")?
             .to_path_buf();
 
         trace!("Parsed config file {:?}", proj.config_file);
+        proj.config_hashes.insert(proj.config_file.to_path_buf(), content_hash(config.as_bytes()));
 
         proj.root = proj.config_file
             .parent()
@@ -73,75 +203,818 @@ impl Project {
 
         debug!("Source root is {:?}", proj.root);
 
-        proj.update_dependencies();
+        let templates = proj.template_root();
+        for (name, module) in proj.modules.iter_mut() {
+            module.name = name.to_string();
+            module.local_name = name.to_string();
+            module.root = proj.root.to_path_buf();
+            module.templates = templates.to_path_buf();
+        }
+
+        loading.push(proj.config_file.to_path_buf());
+        for sub in std::mem::take(&mut proj.subprojects) {
+            proj.add_subproject(&sub, loading)
+                .with_context(|_| format!("loading subproject '{}'", sub.name))?;
+            proj.subprojects.push(sub);
+        }
+        loading.pop();
+
+        let declared: HashSet<&str> = proj.modules
+            .values()
+            .filter_map(|m| match &m.kind {
+                ModuleKind::Executable { target } => Some(target.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        for (target, options) in proj.target_options.iter() {
+            if !declared.contains(target.as_str()) {
+                return Err(format_err!("Options given for unknown target '{}'", target));
+            }
+            if let Some(toolchain) = &options.toolchain {
+                if !proj.toolchains.contains_key(toolchain) {
+                    return Err(format_err!(
+                        "Target '{}' uses unknown toolchain '{}'", target, toolchain));
+                }
+            }
+        }
+
+        for (name, toolchain) in proj.toolchains.iter() {
+            toolchain.check().with_context(|_| format!("checking toolchain '{}'", name))?;
+        }
+
+        for (name, var) in proj.variables.iter() {
+            var.check_declaration(name)?;
+        }
+
+        for (name, value) in std::mem::take(&mut proj.vars) {
+            let value = proj.check_var(&name, value)?;
+            proj.set_var(name, value, Origin::Project);
+        }
+        proj.fill_defaults()?;
+
+        for name in proj.probes.keys() {
+            if proj.variables.contains_key(name) || proj.vars.contains_key(name) {
+                return Err(format_err!("probe '{}' has the same name as a variable", name));
+            }
+        }
+
+        for name in proj.presets.keys() {
+            for (from, preset) in preset::chain(&proj.presets, name)? {
+                for (var, value) in preset.vars.iter() {
+                    proj.check_var(var, value.clone())
+                        .with_context(|_| format!("checking preset '{}'", from))?;
+                }
+            }
+        }
+
+        proj.check_references()?;
         Ok(proj)
     }
 
-    fn update_dependencies(&mut self) {
+    fn add_subproject(&mut self, sub: &Subproject, loading: &mut Vec<PathBuf>) -> Result<()> {
+        let config = sub.config_file(&self.root)?;
+        info!("Loading subproject {} from {:?}", sub.name, config);
+
+        let canonical = std::fs::canonicalize(&config)
+            .with_context(|_| format!("finding {}", config.display()))?;
+        if let Some(start) = loading.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = loading[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format_err!("subproject cycle: {}", cycle.join(" -> ")));
+        }
+
+        let other = Project::load_within(&config, loading)?;
+
+        // Target options belong to the project whose templates build the
+        // targets, which is always the top-level one
+        if let Some(target) = other.target_options.keys().next() {
+            return Err(format_err!(
+                "it sets options for target '{}', which must be set in the top-level project",
+                target));
+        }
+
+        // So do presets and the config header, which cover the whole build
+        if let Some(preset) = other.presets.keys().next() {
+            return Err(format_err!(
+                "it has preset '{}', but only the top-level project may have presets", preset));
+        }
+        if other.config_header.is_some() {
+            return Err(format_err!(
+                "it has a config_header, but only the top-level project may have one"));
+        }
+
+        // Variable declarations, probes and toolchains are shared by the
+        // whole build, so the subproject's are added to this project's
+        if self.variables.is_empty() && !other.variables.is_empty() {
+            if let Some(name) = self.vars.keys().min() {
+                return Err(format_err!(
+                    "it declares its variables, so this project must declare '{}' too", name));
+            }
+        }
+        for (name, var) in other.variables.into_iter() {
+            match self.variables.get(&name) {
+                Some(existing) if *existing != var => return Err(format_err!(
+                    "it declares variable '{}' differently from this project", name)),
+                Some(_) => {},
+                None => { self.variables.insert(name, var); },
+            }
+        }
+
+        for (name, probe) in other.probes.into_iter() {
+            if self.probes.contains_key(&name) {
+                return Err(format_err!("it has probe '{}', which this project also has", name));
+            }
+            self.probes.insert(name, probe);
+        }
+
+        for (name, mut toolchain) in other.toolchains.into_iter() {
+            if self.toolchains.contains_key(&name) {
+                return Err(format_err!("it has toolchain '{}', which this project also has", name));
+            }
+            toolchain.sysroot = toolchain.sysroot
+                .map(|s| sub.path.join(s).to_string_lossy().into_owned());
+            self.toolchains.insert(name, toolchain);
+        }
+
+        // Values from the subproject's `vars` section are defaults for the
+        // ones this project doesn't set itself
+        for (name, value) in other.vars.iter() {
+            if other.origins.get(name) != Some(&Origin::Project) || self.vars.contains_key(name) {
+                continue;
+            }
+            if !self.variables.is_empty() && !self.variables.contains_key(name) {
+                return Err(format_err!(
+                    "it sets variable '{}', which this project doesn't declare", name));
+            }
+            self.vars.insert(name.to_string(), value.clone());
+        }
+
+        for mut overlay in other.overlays.into_iter() {
+            overlay.path = sub.path.join(&overlay.path);
+            self.overlays.push(overlay);
+        }
+
+        let prefix = |n: &str| format!("{}.{}", sub.name, n);
+        let names: HashSet<String> = other.modules.keys().cloned().collect();
+        let local = |n: &str| names.contains(module::split_reference(n).0);
+
+        for (name, mut module) in other.modules.into_iter() {
+            let name = prefix(&name);
+            if self.modules.contains_key(&name) {
+                return Err(format_err!("module '{}' already exists", name));
+            }
+
+            // References to the subproject's own modules get its namespace
+            for dep in module.depends.iter_mut().chain(module.optional_depends.iter_mut()) {
+                if local(dep) {
+                    *dep = prefix(dep);
+                }
+            }
+
+            if let Visibility::Restricted(allowed) = &mut module.visibility {
+                for a in allowed.iter_mut() {
+                    if local(a) {
+                        *a = prefix(a);
+                    }
+                }
+            }
+
+            module.name = name.to_string();
+            self.modules.insert(name, module);
+        }
+
+        self.config_hashes.extend(other.config_hashes);
+        Ok(())
+    }
+
+    /// Resolve which modules are enabled and which targets they belong to
+    /// using the current vars. This is done automatically by `load_vars`,
+    /// and must be done after changing any vars.
+    pub fn resolve(&mut self) -> Result<()> {
+        self.update_dependencies()
+    }
+
+    /// The variables along with the result of every probe, as seen by
+    /// templates and `enabled_if` expressions.
+    fn template_vars(&self) -> HashMap<String, Value> {
+        let mut vars = self.vars.clone();
+        for (name, record) in self.probe_results.iter() {
+            vars.insert(name.to_string(), record.value.clone());
+        }
+        vars
+    }
+
+    /// Check what can be checked without any vars: that every dependency
+    /// names a module and output that exist, and that every `enabled_if`
+    /// parses and, if the project declares its variables, only uses
+    /// declared variables and probes.
+    pub fn check_references(&self) -> Result<()> {
+        let no_vars = HashMap::new();
+        for module in self.modules.values() {
+            if let Some(e) = &module.enabled_if {
+                expr::evaluate(e, &no_vars)
+                    .and_then(|_| self.check_expr_names(e))
+                    .with_context(|_| format!("checking enabled_if for module '{}'", module.name))?;
+            }
+
+            for reference in module.depends.iter().chain(module.optional_depends.iter()) {
+                let (name, output) = module::split_reference(reference);
+                self.modules
+                    .get(name)
+                    .ok_or_else(|| format_err!("module '{}' depends on unknown module '{}'", module.name, name))?
+                    .output(output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that an expression only names declared variables and probes.
+    fn check_expr_names(&self, expr: &str) -> Result<()> {
+        if self.variables.is_empty() {
+            return Ok(());
+        }
+
+        for name in expr::variables(expr)? {
+            if self.variables.contains_key(&name) || self.probes.contains_key(&name) {
+                continue;
+            }
+            return Err(match variable::suggest(&name, self.variables.keys().chain(self.probes.keys())) {
+                Some(s) => format_err!("Unknown variable '{}', did you mean '{}'?", name, s),
+                None => format_err!("Unknown variable '{}'", name),
+            });
+        }
+        Ok(())
+    }
+
+    fn update_dependencies(&mut self) -> Result<()> {
+        // Decide which modules the current vars enable
+        let vars = self.template_vars();
+        for module in self.modules.values_mut() {
+            module.enabled = match &module.enabled_if {
+                Some(e) => expr::evaluate(e, &vars)
+                    .with_context(|_| format!("evaluating enabled_if for module '{}'", module.name))?,
+                None => true,
+            };
+        }
+
+        let enabled: HashSet<String> = self.modules
+            .values()
+            .filter(|m| m.enabled)
+            .map(|m| m.name.to_string())
+            .collect();
+
+        // Required dependencies always apply, optional ones only if enabled
+        for module in self.modules.values_mut() {
+            module.active_depends = module.depends.clone();
+            for reference in &module.optional_depends {
+                if enabled.contains(module::split_reference(reference).0) {
+                    module.active_depends.push(reference.to_string());
+                } else {
+                    debug!("Dropping optional dependency {} of {}", reference, module.name);
+                }
+            }
+        }
+
+        for module in self.modules.values().filter(|m| m.enabled) {
+            for reference in module.active_depends.iter() {
+                let (subdep, output) = module::split_reference(reference);
+                let submodule = self.modules.get(subdep).ok_or_else(|| {
+                    format_err!("module '{}' depends on unknown module '{}'", module.name, subdep)
+                })?;
+                submodule.output(output)?;
+
+                if !submodule.enabled {
+                    return Err(format_err!(
+                        "module '{}' depends on '{}', which is disabled: it requires `{}`",
+                        module.name, subdep, submodule.enabled_if.as_deref().unwrap_or_default()));
+                }
+            }
+        }
+
         // Start each target off with its list of roots
-        for (name, module) in self.modules.iter() {
+        self.targets.clear();
+        for (name, module) in self.modules.iter().filter(|(_, m)| m.enabled) {
             if let ModuleKind::Executable { target } = &module.kind {
                 self.targets
                     .entry(target.to_string())
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(name.to_string());
             }
         }
 
         // Walk the dependency graph and build a list of all required modules for each target
-        for (_, target_modules) in self.targets.iter_mut() {
+        for (target, target_modules) in self.targets.iter_mut() {
             let mut open_list: Vec<String> = target_modules.drain().collect();
 
             while let Some(dep) = open_list.pop() {
                 let module = &self.modules[&dep];
                 target_modules.insert(dep);
 
-                for subdep in module.depends.iter() {
+                for subdep in module.dependency_names() {
+                    let submodule = &self.modules[subdep];
+                    if !submodule.visible_to(module, &[target]) {
+                        return Err(visibility_error(module, submodule, Some(target)));
+                    }
+
                     if !target_modules.contains(subdep) {
                         open_list.push(subdep.to_string());
                     }
                 }
             }
         }
+
+        // Every top-level module shares one namespace, so a private one
+        // there is private to a single target instead
+        for module in self.modules.values() {
+            if module.visibility != Visibility::Private || !module::namespace(&module.name).is_empty() {
+                continue;
+            }
+
+            let mut targets = self.targets_of(&module.name);
+            targets.sort_unstable();
+            if let [first, other, ..] = targets.as_slice() {
+                let from = self.targets[*other]
+                    .iter()
+                    .filter(|n| self.modules[*n].dependency_names().any(|d| d == module.name))
+                    .min()
+                    .unwrap();
+                return Err(format_err!(
+                    "Dependency '{}' -> '{}' (in target '{}') is not allowed: module '{}' is private to target '{}', where it is also used",
+                    from, module.name, other, module.name, first));
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn parse_vars(&mut self, vars: Vec<String>) -> Result<()> {
-        for mut name in vars {
-            if let Some(index) = name.find('=') {
-                let value = name.split_off(index+1);
-                name.pop(); // Strip off the =
-                self.vars.insert(name, value);
-            } else {
-                return Err(format_err!("Variable {} does not parse", name));
+    fn template_root(&self) -> PathBuf {
+        self.root.join(&self.templates)
+    }
+
+    /// The target a module's build file is generated for: an executable's
+    /// own target, or the only target that uses a library. A library used
+    /// by several targets has one build file shared by all of them, so it
+    /// has none.
+    fn module_target<'a>(&'a self, m: &'a Module) -> Option<&'a str> {
+        match &m.kind {
+            ModuleKind::Executable { target } => Some(target),
+            ModuleKind::Library => match self.targets_of(&m.name).as_slice() {
+                [target] => Some(target),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn module_template(&self, m: &Module) -> Result<TemplateChoice> {
+        find_template(
+            &m.templates,
+            &m.local_name,
+            m.kind_name(),
+            self.module_target(m),
+            m.template.as_deref())
+    }
+
+    pub fn target_template(&self, target: &str) -> Result<TemplateChoice> {
+        let explicit = self.target_options.get(target).and_then(|t| t.template.as_deref());
+        find_template(&self.template_root(), target, "target", None, explicit)
+    }
+
+    /// The template chosen for every module and target, sorted by name.
+    pub fn template_choices(&self) -> Result<Vec<(String, TemplateChoice)>> {
+        let mut choices = Vec::new();
+
+        let mut modules: Vec<&Module> = self.modules.values().collect();
+        modules.sort_by(|a, b| a.name.cmp(&b.name));
+        for m in modules.into_iter().filter(|m| m.enabled) {
+            choices.push((format!("module {}", m.name), self.module_template(m)?));
+        }
+
+        let mut targets: Vec<&String> = self.targets.keys().collect();
+        targets.sort();
+        for t in targets {
+            choices.push((format!("target {}", t), self.target_template(t)?));
+        }
+
+        Ok(choices)
+    }
+
+    /// The names of all targets that include the given module.
+    pub fn targets_of(&self, name: &str) -> Vec<&str> {
+        self.targets
+            .iter()
+            .filter(|(_, mods)| mods.contains(name))
+            .map(|(t, _)| t.as_str())
+            .collect()
+    }
+
+    pub fn check_visibility(&self, from: &Module, to: &Module, targets: &[&str]) -> Result<()> {
+        if to.visible_to(from, targets) {
+            Ok(())
+        } else {
+            Err(visibility_error(from, to, None))
+        }
+    }
+
+    /// Check a variable against its declaration, returning the value in
+    /// its normal form. Anything goes if the project declares no variables.
+    fn check_var(&self, name: &str, value: Value) -> Result<Value> {
+        if self.variables.is_empty() {
+            return Ok(value);
+        }
+
+        match self.variables.get(name) {
+            Some(var) => var.check(name, value),
+            None => Err(self.unknown_var(name)),
+        }
+    }
+
+    fn unknown_var(&self, name: &str) -> failure::Error {
+        match variable::suggest(name, self.variables.keys()) {
+            Some(s) => format_err!("Unknown variable '{}', did you mean '{}'?", name, s),
+            None => format_err!("Unknown variable '{}'", name),
+        }
+    }
+
+    /// Set any declared variables that have no value to their defaults.
+    fn fill_defaults(&mut self) -> Result<()> {
+        for (name, var) in self.variables.iter() {
+            if let Some(default) = &var.default {
+                if !self.vars.contains_key(name) {
+                    let value = var.check(name, default.clone())?;
+                    self.vars.insert(name.to_string(), value);
+                    self.origins.insert(name.to_string(), Origin::Default);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_var(&mut self, name: String, value: Value, origin: Origin) {
+        self.origins.insert(name.to_string(), origin);
+        self.vars.insert(name, value);
+    }
+
+    /// The current value of every variable.
+    pub fn vars(&self) -> &HashMap<String, Value> {
+        &self.vars
+    }
+
+    /// The value of a variable, or an error suggesting a declared variable
+    /// if `name` isn't one.
+    pub fn var(&self, name: &str) -> Result<&Value> {
+        if !self.variables.is_empty() && !self.variables.contains_key(name) {
+            return Err(self.unknown_var(name));
+        }
+        self.vars
+            .get(name)
+            .ok_or_else(|| format_err!("Variable '{}' is not set", name))
+    }
+
+    /// Where a variable's current value came from, if known.
+    pub fn origin(&self, name: &str) -> Option<&Origin> {
+        self.origins.get(name)
+    }
+
+    /// Set the variables of the named preset and the presets it inherits.
+    pub fn apply_preset(&mut self, name: &str) -> Result<()> {
+        let mut found = Vec::new();
+        for (from, preset) in preset::chain(&self.presets, name)? {
+            for (var, value) in preset.vars.iter() {
+                let value = self.check_var(var, value.clone())?;
+                found.push((var.to_string(), value, Origin::Preset(from.to_string())));
             }
         }
 
+        for (name, value, origin) in found {
+            self.set_var(name, value, origin);
+        }
+        self.preset = Some(name.to_string());
         Ok(())
     }
 
-    pub fn load_vars(&mut self, build_dir: &Path) -> Result<()> {
-        let mut var_file = build_dir.to_path_buf();
-        var_file.push(VAR_FILE_NAME);
+    /// Apply the variables in a vars file, in order.
+    pub fn read_vars_file(&mut self, path: &Path) -> Result<()> {
+        let contents = std::fs::read(path)
+            .with_context(|_| format!("reading vars file {}", path.display()))?;
+        let full = std::fs::canonicalize(path)
+            .with_context(|_| format!("finding vars file {}", path.display()))?;
+        self.vars_file_hashes.insert(full, content_hash(&contents));
+
+        for entry in vars_file::read(path)? {
+            let vars_file::Entry { line, name, assignment } = entry;
+            let at = match line {
+                0 => path.display().to_string(),
+                n => format!("{}:{}", path.display(), n),
+            };
+            let value = assignment
+                .apply(&name, self.vars.get(&name).cloned())
+                .and_then(|v| self.check_var(&name, v))
+                .map_err(|e| format_err!("{}: {}", at, e))?;
+            self.set_var(name, value, Origin::File(path.display().to_string()));
+        }
+        Ok(())
+    }
 
-        let var_file = std::fs::read_to_string(var_file).context("reading state file")?;
-        self.vars = serde_yaml::from_str(&var_file).context("parsing state file")?;
-        debug!("Loaded existing state: {:?}", self.vars);
+    /// Set variables from the user's config file. Variables this project
+    /// doesn't declare are skipped, since the user config applies to every
+    /// project.
+    pub fn apply_user_config(&mut self, config: &UserConfig) -> Result<()> {
+        for (name, value) in config.vars.iter() {
+            if !self.variables.contains_key(name) {
+                continue;
+            }
 
+            let value = self.check_var(name, value.clone())
+                .with_context(|_| format!("reading user config {}", config.path.display()))?;
+            self.set_var(name.to_string(), value, Origin::User);
+        }
+        Ok(())
+    }
+
+    /// Set variables that are declared to come from the environment from
+    /// any of those environment variables that are set.
+    pub fn import_env(&mut self) -> Result<()> {
+        let mut found = Vec::new();
+        for (name, var) in self.variables.iter() {
+            if let Some(value) = var.from_env() {
+                let env = var.env.as_ref().unwrap();
+                let value = var.check(name, value)
+                    .with_context(|_| format!("reading ${}", env))?;
+                debug!("Setting {} from ${}", name, env);
+                found.push((name.to_string(), value, Origin::Env(env.to_string())));
+            }
+        }
+
+        for (name, value, origin) in found {
+            self.set_var(name, value, origin);
+        }
+        Ok(())
+    }
+
+    /// Set a single variable, checking it against its declaration. Call
+    /// `resolve` once done setting variables.
+    pub fn set_value(&mut self, name: &str, value: Value, origin: Origin) -> Result<()> {
+        let value = self.check_var(name, value)?;
+        self.set_var(name.to_string(), value, origin);
+        Ok(())
+    }
+
+    /// Apply variables given on the command line. Each is one of
+    /// `name=value`, `name:=<yaml value>`, `name+=item` or `name-=item`.
+    /// Call `resolve` once done setting variables, after `run_probes` if
+    /// they may change any probe's result.
+    pub fn parse_vars(&mut self, vars: Vec<String>) -> Result<()> {
+        for arg in vars {
+            let (name, assignment) = variable::parse_assignment(&arg)?;
+            let value = assignment.apply(&name, self.vars.get(&name).cloned())?;
+            let value = self.check_var(&name, value)?;
+            self.set_var(name, value, Origin::Cli);
+        }
+        Ok(())
+    }
+
+    /// Run the project's probes, reusing any results in `previous` whose
+    /// probe, command line and program are unchanged.
+    pub fn run_probes(&mut self, previous: &BTreeMap<String, ProbeRecord>) -> Result<()> {
+        self.probe_results.clear();
+        for (name, probe) in self.probes.iter() {
+            let (record, cached) = probe
+                .evaluate(&self.vars, previous.get(name))
+                .with_context(|_| format!("running probe '{}'", name))?;
+
+            let result = match &record.value {
+                Value::Bool(true) => "yes".to_string(),
+                Value::Bool(false) => "no".to_string(),
+                Value::Null => "not found".to_string(),
+                value => display_value(value),
+            };
+            println!("Probe {}: {}{}", name, result, if cached { " (cached)" } else { "" });
+
+            self.probe_results.insert(name.to_string(), record);
+        }
         Ok(())
     }
 
+    /// Find the tools of every toolchain used by an active target. Must be
+    /// done after `resolve`, as vars decide which targets are built. Returns
+    /// whether anything differs from what was found before.
+    pub fn detect_toolchains(&mut self) -> Result<bool> {
+        let mut used: Vec<&str> = self.targets
+            .keys()
+            .filter_map(|t| self.target_options.get(t).and_then(|o| o.toolchain.as_deref()))
+            .collect();
+        used.sort();
+        used.dedup();
+
+        let vars = self.template_vars();
+        let mut results = BTreeMap::new();
+        for name in used {
+            let found = self.toolchains[name]
+                .detect(name, &self.root, &vars)
+                .with_context(|_| format!("detecting toolchain '{}'", name))?;
+            if self.toolchain_results.get(name) == Some(&found) {
+                results.insert(name.to_string(), found);
+                continue;
+            }
+
+            for (kind, path) in found.tools.iter() {
+                match found.versions.get(kind) {
+                    Some(version) => println!("Toolchain {} {}: {} ({})", name, kind, path.display(), version),
+                    None => println!("Toolchain {} {}: {}", name, kind, path.display()),
+                }
+            }
+            results.insert(name.to_string(), found);
+        }
+
+        let changed = results != self.toolchain_results;
+        self.toolchain_results = results;
+        Ok(changed)
+    }
+
+    /// The detected toolchain a target is built with, if it has one.
+    fn target_toolchain(&self, target: &str) -> Result<Option<&DetectedToolchain>> {
+        let name = match self.target_options.get(target).and_then(|o| o.toolchain.as_ref()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        self.toolchain_results
+            .get(name)
+            .map(Some)
+            .ok_or_else(|| format_err!(
+                "Toolchain '{}' for target '{}' has not been detected, re-run `pb init`", name, target))
+    }
+
+    /// Remove variables set at init time, returning any that have
+    /// defaults to their default values.
+    pub fn unset_vars(&mut self, names: &[String]) -> Result<()> {
+        for name in names {
+            if !self.variables.is_empty() && !self.variables.contains_key(name) {
+                return Err(self.unknown_var(name));
+            }
+            self.origins.remove(name);
+            if self.vars.remove(name).is_none() && self.variables.is_empty() {
+                return Err(format_err!("Variable '{}' is not set", name));
+            }
+        }
+
+        self.fill_defaults()?;
+        self.update_dependencies()
+    }
+
+    /// Whether the build directory has been initialized.
+    pub fn has_state(&self, build_dir: &Path) -> bool {
+        State::exists(build_dir)
+    }
+
+    pub fn load_vars(&mut self, build_dir: &Path) -> Result<()> {
+        let mut state = State::load(build_dir)?;
+        self.vars = HashMap::new();
+        self.origins = HashMap::new();
+        for (name, value) in state.vars {
+            if !self.variables.is_empty() && !self.variables.contains_key(&name) {
+                warn!("Ignoring variable '{}', which the project no longer declares", name);
+                continue;
+            }
+            let value = self.check_var(&name, value)
+                .context("checking saved variables, re-run `pb init` to fix")?;
+            if let Some(origin) = state.origins.remove(&name) {
+                self.origins.insert(name.to_string(), origin);
+            }
+            self.vars.insert(name, value);
+        }
+        self.fill_defaults()?;
+        self.preset = state.preset;
+        self.vars_file_hashes = state.vars_files;
+        self.probe_results = state.probes;
+        self.toolchain_results = state.toolchains;
+        debug!("Loaded existing state: {:?}", self.vars);
+
+        self.update_dependencies()
+    }
+
     pub fn initialize(&self, build_dir: &Path) -> Result<()> {
         info!("Creating build directory at: {:?}", build_dir);
         std::fs::create_dir_all(build_dir).context("creating build output directory")?;
+        self.save_state(build_dir)
+    }
+
+    /// Save the variables and everything found at init time, stamped with
+    /// this version of Bonnibel and the project files' hashes.
+    pub fn save_state(&self, build_dir: &Path) -> Result<()> {
+        let state = State {
+            bonnibel: Some(env!("CARGO_PKG_VERSION").to_string()),
+            project: Some(self.config_file.to_path_buf()),
+            config_hashes: self.config_hashes.clone(),
+            vars_files: self.vars_file_hashes.clone(),
+            vars: self.vars.clone().into_iter().collect(),
+            origins: self.origins.clone().into_iter().collect(),
+            preset: self.preset.clone(),
+            probes: self.probe_results.clone(),
+            toolchains: self.toolchain_results.clone(),
+        };
+        state.save(build_dir)
+    }
 
-        let mut var_file = build_dir.to_path_buf();
-        var_file.push(VAR_FILE_NAME);
+    /// Describe what has changed since the build directory's state was
+    /// saved: the version of Bonnibel, the project or subproject files, or
+    /// the vars files given to `pb init`.
+    /// A build directory initialized from a different project file is an
+    /// error, as regenerating it would mix the two.
+    pub fn changes_since_init(&self, build_dir: &Path) -> Result<Vec<String>> {
+        let state = State::load(build_dir)?;
+
+        if let Some(project) = &state.project {
+            if project != &self.config_file {
+                return Err(format_err!(
+                    "{} was initialized from {}, not {}, re-run `pb init` to switch projects",
+                    build_dir.display(), project.display(), self.config_file.display()));
+            }
+        }
 
-        let mut var_file = std::fs::File::create(var_file)?;
-        serde_yaml::to_writer(&var_file, &self.vars)?;
-        var_file.write(b"\n")?;
+        let mut changes = Vec::new();
+        let current = env!("CARGO_PKG_VERSION");
+        match state.bonnibel.as_deref() {
+            None => changes.push("it was generated by an older version of Bonnibel".to_string()),
+            Some(v) if v != current =>
+                changes.push(format!("it was generated by Bonnibel {}, this is {}", v, current)),
+            Some(_) => {},
+        }
 
-        Ok(())
+        // Old state files have no hashes, and the version change covers them
+        if !state.config_hashes.is_empty() {
+            for (path, hash) in self.config_hashes.iter() {
+                match state.config_hashes.get(path) {
+                    None => changes.push(format!("{} was added", path.display())),
+                    Some(h) if h != hash => changes.push(format!("{} has changed", path.display())),
+                    Some(_) => {},
+                }
+            }
+            for path in state.config_hashes.keys().filter(|p| !self.config_hashes.contains_key(*p)) {
+                changes.push(format!("{} was removed", path.display()));
+            }
+        }
+
+        // Vars files are only read by `pb init`, so a change needs another
+        for (path, hash) in state.vars_files.iter() {
+            match std::fs::read(path) {
+                Err(_) => changes.push(format!(
+                    "vars file {} was removed, re-run `pb init` to stop using it", path.display())),
+                Ok(contents) if &content_hash(&contents) != hash => changes.push(format!(
+                    "vars file {} has changed, re-run `pb init` to apply it", path.display())),
+                Ok(_) => {},
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Write the config header and rustc cfg file if the project asks for
+    /// them, returning their paths.
+    fn write_config_header(&self, build_dir: &Path) -> Result<(Option<PathBuf>, Option<PathBuf>)> {
+        let options = match &self.config_header {
+            Some(options) => options,
+            None => return Ok((None, None)),
+        };
+
+        // Declared variables if there are any, otherwise everything set
+        let mut vars: ConfigVars = if self.variables.is_empty() {
+            self.vars
+                .iter()
+                .map(|(name, value)| (name.as_str(), (None, Some(value))))
+                .collect()
+        } else {
+            self.variables
+                .iter()
+                .map(|(name, var)| (name.as_str(), (Some(var), self.vars.get(name))))
+                .collect()
+        };
+
+        for (name, record) in self.probe_results.iter() {
+            vars.insert(name.as_str(), (None, Some(&record.value)));
+        }
+
+        let header = build_dir.join(&options.path);
+        if config_header::write_if_changed(&header, &options.header(&self.name, &vars))? {
+            info!("Wrote {}", header.display());
+        }
+
+        let rust_cfg = match &options.rust_cfg {
+            Some(path) => {
+                let path = build_dir.join(path);
+                if config_header::write_if_changed(&path, &options.rust_cfg(&vars))? {
+                    info!("Wrote {}", path.display());
+                }
+                Some(path)
+            },
+            None => None,
+        };
+
+        Ok((Some(header), rust_cfg))
     }
 
     pub fn generate(&self, build_dir: &Path) -> Result<()> {
@@ -150,51 +1023,67 @@ impl Project {
 
         println!("Generating build files for {} version {}", self.name, version);
 
-        let mut template_path = self.root.clone();
-        template_path.push(&self.templates);
-        template_path.push("*");
+        let (config_header, rust_cfg) = self.write_config_header(build_dir)?;
+        let template_vars = self.template_vars();
 
-        let tera = Tera::new(template_path.to_str().unwrap())
-            .map_err(tera_failure)
-            .context("parsing templates")?;
+        let mut template_path = self.template_root();
+        let tera = self.load_templates(&template_path)?;
+
+        // Subprojects' modules are rendered with their own templates
+        let mut sub_teras: HashMap<&Path, Tera> = HashMap::new();
+        for m in self.modules.values() {
+            if m.templates != template_path && !sub_teras.contains_key(m.templates.as_path()) {
+                sub_teras.insert(&m.templates, self.load_templates(&m.templates)?);
+            }
+        }
 
-        template_path.pop();
         let mut build_files: Vec<PathBuf> = Vec::new();
         let mut templates: Vec<PathBuf> = Vec::new();
 
-        for (name, m) in &self.modules {
-            let (template_path, template_file) =
-                template_from_options(&template_path, name, m.kind_name())?;
+        for (name, m) in self.modules.iter().filter(|(_, m)| m.enabled) {
+            let choice = self.module_template(m)?;
+            debug!("Module {} uses template {}", name, choice);
 
             let mut build_file = build_dir.to_path_buf();
             build_file.push(format!("{}.ninja", name));
 
-            let mut build_file_out = std::fs::File::create(&build_file)
-                .context("creating build file")?;
-
             let mut ctx = Context::new();
             ctx.insert("module", &m);
             ctx.insert("name", &name);
             ctx.insert("buildfile", &build_file);
-            ctx.insert("vars", &self.vars);
+            ctx.insert("buildroot", &build_dir);
+            ctx.insert("srcroot", &self.root);
+            ctx.insert("vars", &template_vars);
+            ctx.insert("preset", &self.preset);
+            ctx.insert("config_header", &config_header);
+            ctx.insert("rust_cfg", &rust_cfg);
+            ctx.insert("toolchain", &match self.module_target(m) {
+                Some(target) => self.target_toolchain(target)?,
+                None => None,
+            });
             ctx.insert("depmods", &m.depmods(self)?);
             ctx.insert("deplibs", &m.deplibs(self)?);
             ctx.insert("depexes", &m.depexes(self)?);
 
-            let contents = tera.render(template_file.as_str(), ctx)
+            let depoutputs = m.active_depends.iter()
+                .map(|d| Ok((d.as_str(), self.output(d)?)))
+                .collect::<Result<HashMap<&str, &Path>>>()?;
+            ctx.insert("depoutputs", &depoutputs);
+
+            let contents = sub_teras.get(m.templates.as_path()).unwrap_or(&tera)
+                .render(&choice.file, ctx)
                 .map_err(tera_failure)?
                 .into_bytes();
 
-            build_file_out.write_all(&contents)
-                .context("writing build file contents")?;
+            write_atomic(&build_file, &contents)?;
 
             build_files.push(build_file);
-            templates.push(template_path);
+            templates.push(choice.path);
         }
 
         for (target, mods) in &self.targets {
-            let (template_path, template_file) =
-                template_from_options(&template_path, target, "target")?;
+            let choice = self.target_template(target)?;
+            debug!("Target {} uses template {}", target, choice);
 
             let mut target_root = build_dir.to_path_buf();
             target_root.push(target);
@@ -209,20 +1098,22 @@ impl Project {
             ctx.insert("target", &target);
             ctx.insert("modules", &mods);
             ctx.insert("buildfile", &build_file);
-            ctx.insert("vars", &self.vars);
-
-            let contents = tera.render(template_file.as_str(), ctx)
+            ctx.insert("buildroot", &build_dir);
+            ctx.insert("srcroot", &self.root);
+            ctx.insert("vars", &template_vars);
+            ctx.insert("preset", &self.preset);
+            ctx.insert("config_header", &config_header);
+            ctx.insert("rust_cfg", &rust_cfg);
+            ctx.insert("toolchain", &self.target_toolchain(target)?);
+
+            let contents = tera.render(&choice.file, ctx)
                 .map_err(tera_failure)?
                 .into_bytes();
 
-            let mut build_file_out = std::fs::File::create(&build_file)
-                .context("creating build file")?;
-
-            build_file_out.write_all(&contents)
-                .context("writing build file contents")?;
+            write_atomic(&build_file, &contents)?;
 
             build_files.push(build_file);
-            templates.push(template_path);
+            templates.push(choice.path);
         }
 
         template_path.push("build.ninja.j2");
@@ -242,8 +1133,16 @@ impl Project {
 
         let mut ctx = Context::new();
         ctx.insert("targets", &target_names);
-        ctx.insert("modules", &self.modules);
-        ctx.insert("vars", &self.vars);
+        let modules: HashMap<&String, &Module> = self.modules
+            .iter()
+            .filter(|(_, m)| m.enabled)
+            .collect();
+
+        ctx.insert("modules", &modules);
+        ctx.insert("vars", &template_vars);
+        ctx.insert("preset", &self.preset);
+        ctx.insert("config_header", &config_header);
+        ctx.insert("rust_cfg", &rust_cfg);
         ctx.insert("buildroot", &build_dir);
         ctx.insert("srcroot", &self.root);
         ctx.insert("modulefile", &self.config_file);
@@ -269,56 +1168,117 @@ impl Project {
             .map_err(tera_failure)?
             .into_bytes();
 
-        let mut build_file_out = std::fs::File::create(&build_file)
-            .context("creating build file")?;
-
-        build_file_out.write_all(&contents)
-            .context("writing build file contents")?;
+        write_atomic(&build_file, &contents)?;
 
         Ok(())
     }
 
+    fn load_templates(&self, dir: &Path) -> Result<Tera> {
+        let glob = dir.join("*");
+        let mut tera = Tera::new(glob.to_str().unwrap())
+            .map_err(tera_failure)
+            .with_context(|_| format!("parsing templates in {:?}", dir))?;
+
+        let outputs = self.output_map();
+        tera.register_function("output", move |args: &HashMap<String, tera::Value>| {
+            let reference = args.get("ref")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| tera::Error::msg("output() requires a string `ref` argument"))?;
+            outputs.get(reference)
+                .ok_or_else(|| tera::Error::msg(format!("no module output '{}'", reference)))
+                .and_then(|p| tera::to_value(p).map_err(tera::Error::json))
+        });
+
+        Ok(tera)
+    }
+
+    /// The JSON Schema describing project files.
+    pub fn schema() -> schemars::schema::RootSchema {
+        schemars::schema_for!(Project)
+    }
+
     pub fn module(&self, name: &str) -> Result<&Module> {
         self.modules.get(name).ok_or(err_msg("couldn't find module"))
     }
-}
 
-fn template_from_options(root: &Path, name: &str, kind: &str) -> Result<(PathBuf, String)> {
-    let mut path = root.to_path_buf();
-    let file = format!("{}.{}.j2", kind, name);
-    path.push(&file);
-
-    if path.exists() {
-        Ok((path, file))
-    } else {
-        let file = format!("{}.default.j2", kind);
-        path.set_file_name(&file);
-        if path.exists() {
-            Ok((path, file))
-        } else {
-            Err(format_err!("Missing template for module '{}'.", name))
-        }
+    /// Resolve an output reference like "kernel" or "kernel:symbols" to a path.
+    pub fn output(&self, reference: &str) -> Result<&Path> {
+        let (name, output) = module::split_reference(reference);
+        self.modules
+            .get(name)
+            .ok_or_else(|| format_err!("no module named '{}' for output '{}'", name, reference))?
+            .output(output)
     }
+
+    fn output_map(&self) -> HashMap<String, PathBuf> {
+        self.modules
+            .values()
+            .flat_map(|m| m.output_references())
+            .map(|(r, p)| (r, p.to_path_buf()))
+            .collect()
+    }
+}
+
+fn visibility_error(from: &Module, to: &Module, target: Option<&str>) -> failure::Error {
+    let edge = match target {
+        Some(t) => format!("'{}' -> '{}' (in target '{}')", from.name, to.name, t),
+        None => format!("'{}' -> '{}'", from.name, to.name),
+    };
+
+    let rule = match &to.visibility {
+        Visibility::Private => {
+            let ns = module::namespace(&to.name);
+            if ns.is_empty() {
+                "is private to the top-level namespace".to_string()
+            } else {
+                format!("is private to namespace '{}'", ns)
+            }
+        },
+        v => format!("is {}", v),
+    };
+
+    format_err!("Dependency {} is not allowed: module '{}' {}", edge, to.name, rule)
 }
 
+/// Get the project version from git. Projects that aren't in a git
+/// repository, or have no tags yet, are version 0.0.0.
 fn get_version(root: &Path) -> Result<Version> {
-    let repo = Repository::open(root)
-        .context("opening git repository")?;
-
-    let desc = repo
-        .describe(DescribeOptions::new().max_candidates_tags(100))
-        .context("getting git description")?
-        .format(Some(DescribeFormatOptions::new()
-                     .abbreviated_size(0)
-                     .dirty_suffix("+dirty")))
-        .context("formatting git description")?;
-
-    let mut v = Version::parse(desc.as_str())
-        .unwrap_or_else(|_| Version::new(0,0,0));
-
-    let head = repo
-        .revparse_single("HEAD")
-        .context("finding git head revision")?
+    let mut unknown = Version::new(0,0,0);
+    unknown.build.push(Identifier::AlphaNumeric("unknown".to_string()));
+
+    let repo = match Repository::open(root) {
+        Ok(repo) => repo,
+        Err(e) => {
+            warn!("Couldn't open git repository for version info: {}", e.message());
+            return Ok(unknown);
+        },
+    };
+
+    let head = match repo.revparse_single("HEAD") {
+        Ok(head) => head,
+        Err(e) => {
+            warn!("Couldn't find git HEAD for version info: {}", e.message());
+            return Ok(unknown);
+        },
+    };
+
+    let desc = match repo.describe(DescribeOptions::new().max_candidates_tags(100)) {
+        Ok(desc) => Some(desc
+            .format(Some(DescribeFormatOptions::new()
+                         .abbreviated_size(0)
+                         .dirty_suffix("+dirty")))
+            .context("formatting git description")?),
+        Err(e) => {
+            warn!("Couldn't describe git HEAD for version info: {}", e.message());
+            None
+        },
+    };
+
+    let mut v = desc
+        .and_then(|d| Version::parse(d.as_str()).ok())
+        .unwrap_or_else(|| Version::new(0,0,0));
+
+    let head = head
         .short_id()
         .context("getting git head revision id")?;
 
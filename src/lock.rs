@@ -0,0 +1,95 @@
+//! Keeping two `pb` processes from changing a build directory at once, and
+//! anything reading it from seeing a half-written file.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use failure::ResultExt;
+use fs2::FileExt;
+use log::warn;
+use tempfile::Builder;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+pub const LOCK_FILE_NAME: &str = ".bonnibel_lock";
+
+/// An advisory lock on a build directory, held until it is dropped.
+#[derive(Debug)]
+pub struct BuildLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl BuildLock {
+    /// Lock the build directory, creating it if needed, and waiting for any
+    /// other `pb` that has it locked.
+    pub fn acquire(build_dir: &Path) -> Result<BuildLock> {
+        std::fs::create_dir_all(build_dir).context("creating build output directory")?;
+
+        let path = build_dir.join(LOCK_FILE_NAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|_| format!("opening {}", path.display()))?;
+
+        if file.try_lock_exclusive().is_err() {
+            eprintln!("Waiting for another pb to finish with {}...", build_dir.display());
+            file.lock_exclusive()
+                .with_context(|_| format!("locking {}", build_dir.display()))?;
+        }
+
+        Ok(BuildLock { file, path })
+    }
+}
+
+impl Drop for BuildLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.unlock() {
+            warn!("Couldn't unlock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Replace the file at `path` with `contents` by writing a temporary file
+/// beside it and renaming it into place, so the file is never seen half
+/// written, even if `pb` is interrupted.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("bonnibel");
+
+    // Temporary files are only readable by their owner, so a new file is
+    // created the way any other would be, subject to the umask, and one
+    // being replaced keeps its permissions
+    let existing = std::fs::metadata(path).ok().map(|m| m.permissions());
+    let prefix = format!(".{}", name);
+    let mut builder = Builder::new();
+    builder.prefix(&prefix);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if existing.is_none() {
+            builder.permissions(std::fs::Permissions::from_mode(0o666));
+        }
+    }
+
+    let mut file = builder.tempfile_in(dir)
+        .with_context(|_| format!("creating {}", path.display()))?;
+    file.write_all(contents)
+        .with_context(|_| format!("writing {}", path.display()))?;
+
+    if let Some(permissions) = existing {
+        file.as_file()
+            .set_permissions(permissions)
+            .with_context(|_| format!("setting permissions of {}", path.display()))?;
+    }
+
+    file.persist(path)
+        .with_context(|_| format!("replacing {}", path.display()))?;
+    Ok(())
+}
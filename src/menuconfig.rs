@@ -0,0 +1,354 @@
+//! An interactive terminal menu for editing a build directory's variables,
+//! in the spirit of the Linux kernel's `make menuconfig`.
+
+use console::{style, Key, Term};
+use failure::{err_msg, format_err};
+use serde_yaml::Value;
+
+use super::variable::display;
+use super::{Origin, Project, VarType};
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+const HELP: &str = "up/down move, space/enter change, left/right cycle choices, d default, s save, q quit";
+
+/// Lines used by the title and the description pane.
+const CHROME_LINES: usize = 8;
+
+struct Menu<'a> {
+    project: &'a mut Project,
+    names: Vec<String>,
+    selected: usize,
+    top: usize,
+    modified: bool,
+    status: Option<String>,
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+impl<'a> Menu<'a> {
+    fn current(&self) -> &str {
+        &self.names[self.selected]
+    }
+
+    fn value(&self, name: &str) -> Option<&Value> {
+        self.project.vars().get(name)
+    }
+
+    fn row(&self, name: &str) -> String {
+        let var = &self.project.variables[name];
+        match (var.kind, self.value(name)) {
+            (VarType::Bool, Some(Value::Bool(true))) => format!("[x] {}", name),
+            (VarType::Bool, _) => format!("[ ] {}", name),
+            (_, Some(value)) => format!("    {} = {}", name, display(value)),
+            (_, None) => format!("    {} (not set)", name),
+        }
+    }
+
+    fn draw(&mut self, term: &Term) -> Result<()> {
+        let (rows, cols) = term.size();
+        let (rows, cols) = (rows as usize, cols as usize);
+        let visible = rows.saturating_sub(CHROME_LINES).max(1);
+
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + visible {
+            self.top = self.selected + 1 - visible;
+        }
+
+        term.clear_screen()?;
+        term.write_line(&truncate(&format!("{} configuration", self.project.name), cols))?;
+        term.write_line(&style(truncate(HELP, cols)).dim().to_string())?;
+        term.write_line("")?;
+
+        for (i, name) in self.names.iter().enumerate().skip(self.top).take(visible) {
+            let row = truncate(&self.row(name), cols);
+            if i == self.selected {
+                term.write_line(&style(row).reverse().to_string())?;
+            } else {
+                term.write_line(&row)?;
+            }
+        }
+
+        let name = self.current();
+        let var = &self.project.variables[name];
+        let mut about = format!("{} ({})", name, var.kind);
+        if !var.choices.is_empty() {
+            about.push_str(&format!(": {}", var.choices.join(", ")));
+        }
+        if let Some(origin) = self.project.origin(name) {
+            about.push_str(&format!(", from {}", origin));
+        }
+
+        term.write_line(&"-".repeat(cols))?;
+        term.write_line(&style(truncate(&about, cols)).bold().to_string())?;
+        term.write_line(&truncate(var.help.as_deref().unwrap_or(""), cols))?;
+        if let Some(status) = &self.status {
+            term.write_str(&style(truncate(status, cols)).red().to_string())?;
+        }
+        term.flush()?;
+        Ok(())
+    }
+
+    fn set(&mut self, value: Value) {
+        let name = self.current().to_string();
+        match self.project.set_value(&name, value, Origin::Menu) {
+            Ok(()) => self.modified = true,
+            Err(e) => self.status = Some(e.to_string()),
+        }
+    }
+
+    /// Step through the choices of an enum, or flip a bool.
+    fn cycle(&mut self, forward: bool) -> bool {
+        let name = self.current();
+        let var = &self.project.variables[name];
+        let value = match (var.kind, self.value(name)) {
+            (VarType::Bool, Some(Value::Bool(b))) => Value::Bool(!b),
+            (VarType::Bool, _) => Value::Bool(true),
+            (_, value) if !var.choices.is_empty() && var.kind != VarType::List => {
+                let n = var.choices.len();
+                let current = value
+                    .and_then(|v| var.choices.iter().position(|c| Some(c.as_str()) == v.as_str()));
+                let next = match (current, forward) {
+                    (None, _) => 0,
+                    (Some(i), true) => (i + 1) % n,
+                    (Some(i), false) => (i + n - 1) % n,
+                };
+                Value::String(var.choices[next].to_string())
+            },
+            _ => return false,
+        };
+
+        self.set(value);
+        true
+    }
+
+    /// Prompt for a new value on the line below the menu.
+    fn edit(&mut self, term: &Term) -> Result<()> {
+        let name = self.current().to_string();
+        let current = self.value(&name).map(display).unwrap_or_default();
+
+        term.write_line("")?;
+        term.write_str(&format!("{} [{}]: ", name, current))?;
+        let input = term.read_line()?;
+        self.enter(input.trim());
+        Ok(())
+    }
+
+    /// Set the current variable from what was typed at the prompt, leaving
+    /// it alone if nothing was.
+    fn enter(&mut self, input: &str) {
+        if input.is_empty() {
+            return;
+        }
+
+        let name = self.current();
+        let value = match self.project.variables[name].kind {
+            // Lists can be given as bare items, like on the command line
+            VarType::List if !input.starts_with('[') => Value::Sequence(
+                input
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|i| !i.is_empty())
+                    .map(|i| Value::String(i.to_string()))
+                    .collect()),
+
+            VarType::List | VarType::Map => match serde_yaml::from_str(input) {
+                Ok(value) => value,
+                Err(e) => {
+                    self.status = Some(format!("{} does not parse: {}", name, e));
+                    return;
+                },
+            },
+            _ => Value::String(input.to_string()),
+        };
+
+        self.set(value);
+    }
+
+    /// Flip or cycle the current variable if it has a fixed set of values,
+    /// otherwise prompt for a new one.
+    fn change(&mut self, term: &Term) -> Result<()> {
+        if !self.cycle(true) {
+            self.edit(term)?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        let name = self.current().to_string();
+        match self.project.unset_vars(&[name]) {
+            Ok(()) => self.modified = true,
+            Err(e) => self.status = Some(e.to_string()),
+        }
+    }
+
+    fn confirm_quit(&mut self, term: &Term) -> Result<Option<bool>> {
+        if !self.modified {
+            return Ok(Some(false));
+        }
+
+        term.write_line("")?;
+        term.write_str("Save changes? [y/n] ")?;
+        Ok(match term.read_key()? {
+            Key::Char('y') | Key::Char('Y') => Some(true),
+            Key::Char('n') | Key::Char('N') => Some(false),
+            _ => None,
+        })
+    }
+
+    fn run(&mut self, term: &Term) -> Result<bool> {
+        loop {
+            self.draw(term)?;
+            let key = term.read_key()?;
+            self.status = None;
+
+            match key {
+                Key::ArrowUp | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+                Key::ArrowDown | Key::Char('j') =>
+                    self.selected = (self.selected + 1).min(self.names.len() - 1),
+                Key::ArrowRight => { self.cycle(true); },
+                Key::ArrowLeft => { self.cycle(false); },
+                Key::Enter | Key::Char(' ') => self.change(term)?,
+                Key::Char('d') => self.reset(),
+                Key::Char('s') => return Ok(true),
+                Key::Char('q') | Key::Escape => {
+                    if let Some(save) = self.confirm_quit(term)? {
+                        return Ok(save);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Let the user edit the project's declared variables in the terminal.
+/// Returns whether the changes should be saved.
+pub fn menuconfig(project: &mut Project) -> Result<bool> {
+    let term = Term::stdout();
+    if !term.is_term() {
+        return Err(err_msg("menuconfig needs an interactive terminal"));
+    }
+
+    if project.variables.is_empty() {
+        return Err(format_err!("{} declares no variables to configure", project.name));
+    }
+
+    let names = project.variables.keys().cloned().collect();
+    let mut menu = Menu {
+        project,
+        names,
+        selected: 0,
+        top: 0,
+        modified: false,
+        status: None,
+    };
+
+    let result = menu.run(&term);
+    term.clear_screen()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = r#"
+name: test
+templates: templates
+variables:
+  debug:
+    type: bool
+    default: false
+  arch:
+    type: enum
+    choices: [x86_64, aarch64, riscv64]
+    default: x86_64
+  jobs:
+    type: int
+    default: 1
+  cflags:
+    type: list
+    default: []
+modules: {}
+"#;
+
+    fn project() -> Project {
+        let mut project: Project = serde_yaml::from_str(PROJECT).unwrap();
+        project.fill_defaults().unwrap();
+        project
+    }
+
+    fn menu<'a>(project: &'a mut Project, name: &str) -> Menu<'a> {
+        let names: Vec<String> = project.variables.keys().cloned().collect();
+        let selected = names.iter().position(|n| n == name).unwrap();
+        Menu { project, names, selected, top: 0, modified: false, status: None }
+    }
+
+    fn value(menu: &Menu, name: &str) -> Value {
+        menu.value(name).cloned().unwrap()
+    }
+
+    #[test]
+    fn cycle() {
+        let mut project = project();
+        let mut menu = menu(&mut project, "debug");
+        assert!(menu.cycle(true));
+        assert_eq!(value(&menu, "debug"), Value::Bool(true));
+        assert!(menu.cycle(false));
+        assert_eq!(value(&menu, "debug"), Value::Bool(false));
+
+        menu.selected = menu.names.iter().position(|n| n == "arch").unwrap();
+        assert!(menu.cycle(true));
+        assert_eq!(value(&menu, "arch"), Value::String("aarch64".to_string()));
+        assert!(menu.cycle(false));
+        assert!(menu.cycle(false));
+        assert_eq!(value(&menu, "arch"), Value::String("riscv64".to_string()));
+        assert!(menu.modified);
+
+        // Anything else has to be typed
+        menu.selected = menu.names.iter().position(|n| n == "jobs").unwrap();
+        assert!(!menu.cycle(true));
+
+        assert_eq!(project.origin("arch"), Some(&Origin::Menu));
+    }
+
+    #[test]
+    fn edit() {
+        let mut project = project();
+        let mut menu = menu(&mut project, "jobs");
+        menu.enter("");
+        assert!(!menu.modified);
+
+        menu.enter("8");
+        assert_eq!(value(&menu, "jobs"), serde_yaml::from_str::<Value>("8").unwrap());
+
+        menu.enter("many");
+        assert!(menu.status.take().unwrap().contains("jobs"));
+        assert_eq!(value(&menu, "jobs"), serde_yaml::from_str::<Value>("8").unwrap());
+
+        // Lists take bare items or YAML
+        menu.selected = menu.names.iter().position(|n| n == "cflags").unwrap();
+        menu.enter("-O2, -g");
+        assert_eq!(value(&menu, "cflags"), serde_yaml::from_str::<Value>("[-O2, -g]").unwrap());
+        menu.enter("[-Os]");
+        assert_eq!(value(&menu, "cflags"), serde_yaml::from_str::<Value>("[-Os]").unwrap());
+        menu.enter("[-Os");
+        assert!(menu.status.take().unwrap().contains("does not parse"));
+    }
+
+    #[test]
+    fn reset() {
+        let mut project = project();
+        let mut menu = menu(&mut project, "arch");
+        menu.enter("aarch64");
+        menu.modified = false;
+
+        menu.reset();
+        assert!(menu.modified);
+        assert_eq!(value(&menu, "arch"), Value::String("x86_64".to_string()));
+        assert_eq!(project.origin("arch"), Some(&Origin::Default));
+    }
+}
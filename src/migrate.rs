@@ -0,0 +1,204 @@
+use failure::{format_err, ResultExt};
+use log::warn;
+use schemars::JsonSchema;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use super::edit::{scalar, YamlEditor};
+use super::ConfigFormat;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// The current version of the project file format.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Project files without a header are the original format.
+const UNVERSIONED_SCHEMA: u32 = 1;
+
+/// Describes which format a project file is written in and which versions
+/// of Bonnibel can read it.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct Header {
+    /// The version of the project file format
+    pub schema: u32,
+
+    /// A semver requirement on the Bonnibel version, eg ">=2.4"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires: Option<String>,
+}
+
+impl Header {
+    pub fn current() -> Header {
+        let version = this_version();
+        Header {
+            schema: SCHEMA_VERSION,
+            requires: Some(format!(">={}.{}", version.major, version.minor)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default)]
+    bonnibel: Option<Header>,
+}
+
+fn this_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("crate version is not semver")
+}
+
+/// Read just the header of a project file and make sure this version of
+/// Bonnibel can understand the rest of it. Returns the file's schema version.
+pub fn check_header(contents: &str, format: ConfigFormat) -> Result<u32> {
+    let versioned: Versioned = format.parse(contents).context("parsing bonnibel header")?;
+    let header = match versioned.bonnibel {
+        Some(header) => header,
+        None => return Ok(UNVERSIONED_SCHEMA),
+    };
+
+    if let Some(requires) = &header.requires {
+        let req = VersionReq::parse(requires)
+            .with_context(|_| format!("parsing bonnibel version requirement '{}'", requires))?;
+
+        let version = this_version();
+        if !req.matches(&version) {
+            return Err(format_err!(
+                "This project requires bonnibel {}, but this is version {}", requires, version));
+        }
+    }
+
+    if header.schema > SCHEMA_VERSION {
+        return Err(format_err!(
+            "This project file uses schema {}, but this bonnibel only understands up to schema {}. \
+             Please upgrade bonnibel.", header.schema, SCHEMA_VERSION));
+    }
+
+    if header.schema < SCHEMA_VERSION {
+        warn!("Project file uses schema {}, run `pb migrate` to update it to schema {}",
+            header.schema, SCHEMA_VERSION);
+    }
+
+    Ok(header.schema)
+}
+
+fn key(k: &str) -> Value {
+    Value::String(k.to_string())
+}
+
+/// Schema 1 to 2: add the header and use canonical key names. Changes are
+/// made to the parsed document, and also to `editor` if the file is being
+/// edited as text.
+fn migrate_v1(
+    root: &mut Mapping,
+    mut editor: Option<&mut YamlEditor>,
+    changes: &mut Vec<String>,
+) -> Result<()> {
+    let modules = match root.get_mut(&key("modules")) {
+        Some(Value::Mapping(modules)) => modules,
+        _ => return Ok(()),
+    };
+
+    for (name, module) in modules.iter_mut() {
+        let name = name.as_str().unwrap_or("?");
+        let module = match module {
+            Value::Mapping(module) => module,
+            _ => continue,
+        };
+        let deps = match module.remove(&key("deps")) {
+            Some(deps) => deps,
+            None => continue,
+        };
+
+        match module.get_mut(&key("depends")) {
+            None => {
+                module.insert(key("depends"), deps);
+                if let Some(editor) = editor.as_deref_mut() {
+                    editor.rename_module_key(name, "deps", "depends")?;
+                }
+                changes.push(format!("renamed `deps` to `depends` in module '{}'", name));
+            },
+
+            Some(Value::Sequence(depends)) => {
+                let deps = match deps {
+                    Value::Sequence(deps) => deps,
+                    _ => return Err(format_err!("`deps` of module '{}' is not a list", name)),
+                };
+
+                let added: Vec<Value> = deps.into_iter().filter(|d| !depends.contains(d)).collect();
+                if let Some(editor) = editor.as_deref_mut() {
+                    editor.remove_module_key(name, "deps")?;
+                    for dep in added.iter() {
+                        let dep = dep.as_str()
+                            .ok_or_else(|| format_err!("`deps` of module '{}' is not a list of names", name))?;
+                        editor.add_dependency(name, dep)?;
+                    }
+                }
+                depends.extend(added);
+                changes.push(format!("merged `deps` into `depends` in module '{}'", name));
+            },
+
+            Some(_) => return Err(format_err!("`depends` of module '{}' is not a list", name)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrite a project file's contents into the current schema. Returns the
+/// new contents and a description of each change made, or None if the
+/// file is already current.
+pub fn migrate(contents: &str, format: ConfigFormat) -> Result<Option<(String, Vec<String>)>> {
+    let from = check_header(contents, format)?;
+    if from == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    let mut value: Value = format.parse(contents)?;
+    let root = match &mut value {
+        Value::Mapping(m) => m,
+        _ => return Err(format_err!("project file is not a mapping")),
+    };
+
+    // YAML files are edited as text, so that comments and formatting are
+    // kept. Other formats are written out again from the parsed document.
+    let mut editor = match format {
+        ConfigFormat::Yaml => Some(YamlEditor::new(contents)),
+        ConfigFormat::Toml => {
+            warn!("Migrating rewrites the whole file: comments and formatting will be lost");
+            None
+        },
+        ConfigFormat::Json => None,
+    };
+
+    let mut changes = Vec::new();
+    if from <= 1 {
+        migrate_v1(root, editor.as_mut(), &mut changes)?;
+    }
+
+    let current = Header::current();
+    changes.push(format!("set schema version {} -> {}", from, SCHEMA_VERSION));
+
+    if let Some(mut editor) = editor {
+        let mut fields = vec![("schema", current.schema.to_string())];
+        if let Some(requires) = &current.requires {
+            fields.push(("requires", scalar(requires)));
+        }
+        editor.set_top_level("bonnibel", &fields)?;
+        return Ok(Some((editor.to_string(), changes)));
+    }
+
+    // The header always goes first
+    let header = serde_yaml::to_value(current)?;
+
+    let mut migrated = Mapping::new();
+    migrated.insert(key("bonnibel"), header);
+    for (k, v) in root.iter() {
+        if k != &key("bonnibel") {
+            migrated.insert(k.clone(), v.clone());
+        }
+    }
+
+    let output = format.write(&Value::Mapping(migrated))?;
+    Ok(Some((output, changes)))
+}
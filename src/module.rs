@@ -1,6 +1,10 @@
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
+use failure::format_err;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use super::Project;
@@ -24,55 +28,191 @@ impl From<PathBuf> for SourceItem {
             name: s.file_name().map(|s| s.to_str().unwrap()).unwrap_or("unnamed").to_string(),
             output: s.with_extension(format!("{}.o", action)),
             input: s,
-            action: action,
+            action,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+#[serde(untagged)]
+enum VisibilitySpec {
+    /// "public" or "private"
+    Keyword(String),
+
+    /// The modules, namespaces, or targets allowed to depend on this module
+    List(Vec<String>),
+}
+
+/// Which other modules may depend on a module. A private module may only be
+/// used from its own namespace, or in the top-level project, which is all
+/// one namespace, from a single target. A restricted list may name modules,
+/// namespaces, or targets.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "VisibilitySpec", into = "VisibilitySpec")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Private,
+    Restricted(Vec<String>),
+}
+
+impl TryFrom<VisibilitySpec> for Visibility {
+    type Error = String;
+
+    fn try_from(spec: VisibilitySpec) -> std::result::Result<Self, Self::Error> {
+        match spec {
+            VisibilitySpec::Keyword(k) => match k.as_str() {
+                "public" => Ok(Visibility::Public),
+                "private" => Ok(Visibility::Private),
+                _ => Err(format!("unknown visibility '{}', expected public, private, or a list", k)),
+            },
+            VisibilitySpec::List(l) => Ok(Visibility::Restricted(l)),
+        }
+    }
+}
+
+impl From<Visibility> for VisibilitySpec {
+    fn from(v: Visibility) -> Self {
+        match v {
+            Visibility::Public => VisibilitySpec::Keyword("public".to_string()),
+            Visibility::Private => VisibilitySpec::Keyword("private".to_string()),
+            Visibility::Restricted(l) => VisibilitySpec::List(l),
+        }
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Visibility::Public => write!(f, "public"),
+            Visibility::Private => write!(f, "private"),
+            Visibility::Restricted(l) => write!(f, "visible only to [{}]", l.join(", ")),
+        }
+    }
+}
+
+/// Split a dependency reference like "kernel:symbols" into the module
+/// name and the optional output name.
+pub fn split_reference(reference: &str) -> (&str, Option<&str>) {
+    match reference.find(':') {
+        Some(i) => (&reference[..i], Some(&reference[i+1..])),
+        None => (reference, None),
+    }
+}
+
+/// The namespace part of a module name, eg "libc" for "libc.ctype".
+pub fn namespace(name: &str) -> &str {
+    name.rfind('.').map(|i| &name[..i]).unwrap_or("")
+}
+
+/// A library or executable to be built.
+#[derive(Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
 pub struct Module {
+    #[serde(skip_deserializing)]
+    #[schemars(skip)]
+    pub name: String,
+
+    /// The module's name within the project that defined it
+    #[serde(skip)]
+    pub local_name: String,
+
+    /// The source root of the project that defined this module
+    #[serde(skip_deserializing)]
+    #[schemars(skip)]
+    pub root: PathBuf,
+
+    #[serde(skip)]
+    pub templates: PathBuf,
+
+    /// Modules this module depends on, optionally naming an output as "module:output"
     #[serde(alias = "deps")]
     #[serde(default)]
     pub depends: Vec<String>,
 
+    /// Dependencies that are dropped if the module they name is disabled
+    #[serde(default)]
+    pub optional_depends: Vec<String>,
+
+    #[serde(skip_deserializing)]
+    #[schemars(skip)]
+    pub active_depends: Vec<String>,
+
+    /// An expression over vars that must be true for this module to be built
+    #[serde(default)]
+    pub enabled_if: Option<String>,
+
+    #[serde(skip_deserializing)]
+    #[schemars(skip)]
+    pub enabled: bool,
+
+    /// Extra files passed along to the module's template
     #[serde(default)]
     pub extras: Vec<PathBuf>,
 
+    /// Preprocessor definitions
     #[serde(default)]
     defines: Vec<String>,
 
+    /// Include directories
     #[serde(default)]
     includes: Vec<PathBuf>,
 
+    /// Source files, relative to the project root
+    #[schemars(with = "Vec<PathBuf>")]
     source: Vec<SourceItem>,
 
+    /// The primary output file
     output: PathBuf,
 
+    /// Additional named outputs, referenced as "module:name"
+    #[serde(default)]
+    outputs: BTreeMap<String, PathBuf>,
+
+    /// Which modules may depend on this one: "public", "private", or a list
+    /// of modules, namespaces, or targets
+    #[serde(default)]
+    #[schemars(with = "VisibilitySpec")]
+    pub visibility: Visibility,
+
+    /// The template file to render this module with
+    #[serde(default)]
+    pub template: Option<String>,
+
     #[serde(flatten)]
     pub kind: ModuleKind,
 }
 
-#[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+/// The kind of module, set with the `kind` key.
+#[derive(Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
 #[serde(tag = "kind")]
 pub enum ModuleKind {
+    /// A library linked into the executables that depend on it
     #[serde(rename = "lib")]
     Library,
 
+    /// An executable built for a target
     #[serde(rename = "exe")]
-    Executable { target: String },
+    Executable {
+        /// The target to build this executable for
+        target: String,
+    },
 }
 
 impl Module {
     pub fn depmods<'a>(&self, proj: &'a Project) -> Result<Vec<&'a Module>> {
-        let mut open = self.depends.iter()
-            .map(|n| proj.module(n))
-            .collect::<Result<Vec<&'a Module>>>()?;
+        let mut open = Vec::new();
+        for name in self.dependency_names() {
+            let dep = proj.module(name)?;
+            proj.check_visibility(self, dep, &proj.targets_of(&self.name))?;
+            open.push(dep);
+        }
 
         let mut closed = HashSet::new();
         while let Some(module) = open.pop() {
             closed.insert(module);
-            for dep in &module.depends {
-                let dep = proj.module(&dep)?;
+            for dep in module.dependency_names() {
+                let dep = proj.module(dep)?;
+                proj.check_visibility(module, dep, &proj.targets_of(&module.name))?;
                 if !closed.contains(dep) {
                     open.push(dep);
                 }
@@ -85,23 +225,64 @@ impl Module {
     pub fn deplibs<'a>(&self, proj: &'a Project) -> Result<Vec<&'a Module>> {
         Ok(self.depmods(proj)?
            .iter()
-           .filter(|m| match m.kind { ModuleKind::Library { .. } => true, _ => false })
-           .map(|m| *m)
+           .filter(|m| matches!(m.kind, ModuleKind::Library))
+           .copied()
            .collect())
     }
 
     pub fn depexes<'a>(&self, proj: &'a Project) -> Result<Vec<&'a Module>> {
         Ok(self.depmods(proj)?
            .iter()
-           .filter(|m| match m.kind { ModuleKind::Executable { .. } => true, _ => false })
-           .map(|m| *m)
+           .filter(|m| matches!(m.kind, ModuleKind::Executable { .. }))
+           .copied()
            .collect())
     }
 
+    /// The names of the modules this module depends on, without any
+    /// output names.
+    pub fn dependency_names(&self) -> impl Iterator<Item = &str> {
+        self.active_depends.iter().map(|d| split_reference(d).0)
+    }
+
+    /// The path of the named output, or the primary output if no name is given.
+    pub fn output(&self, name: Option<&str>) -> Result<&Path> {
+        match name {
+            None => Ok(&self.output),
+            Some(n) => self.outputs
+                .get(n)
+                .map(|p| p.as_path())
+                .ok_or_else(|| format_err!("module '{}' has no output named '{}'", self.name, n)),
+        }
+    }
+
+    /// All outputs of this module keyed by their reference, ie "name" for
+    /// the primary output and "name:output" for each named output.
+    pub fn output_references(&self) -> Vec<(String, &Path)> {
+        let mut refs = vec![(self.name.to_string(), self.output.as_path())];
+        for (n, p) in &self.outputs {
+            refs.push((format!("{}:{}", self.name, n), p.as_path()));
+        }
+        refs
+    }
+
+    /// Whether `from`, built for `targets`, is allowed to depend on this module.
+    pub fn visible_to(&self, from: &Module, targets: &[&str]) -> bool {
+        match &self.visibility {
+            Visibility::Public => true,
+            Visibility::Private => namespace(&from.name) == namespace(&self.name),
+            Visibility::Restricted(allowed) => {
+                let allows = |n: &str| allowed.iter().any(|a| a == n);
+                allows(&from.name)
+                    || (!namespace(&from.name).is_empty() && allows(namespace(&from.name)))
+                    || (!targets.is_empty() && targets.iter().all(|t| allows(t)))
+            },
+        }
+    }
+
     pub fn kind_name(&self) -> &str {
         match self.kind {
             ModuleKind::Executable { .. } => "exe",
-            ModuleKind::Library { .. } => "lib",
+            ModuleKind::Library => "lib",
         }
     }
 }
@@ -1,4 +1,5 @@
 use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -6,13 +7,18 @@ use std::process::Command;
 
 use failure::{format_err, ResultExt};
 use reqwest::Url;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use tempfile::NamedTempFile;
 type Result<T> = std::result::Result<T, failure::Error>;
 
-#[derive(Debug, Deserialize)]
+/// An archive downloaded and extracted into the source tree.
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Overlay {
+    /// The URL of the archive to download
     pub url: String,
+
+    /// Where to extract the archive, relative to the project root
     pub path: PathBuf,
 
     #[serde(skip)]
@@ -23,6 +29,9 @@ pub struct Overlay {
 
     #[serde(skip)]
     pub filename: String,
+
+    #[serde(skip)]
+    mirror: Option<String>,
 }
 
 struct ProgressWriter<W, F> where
@@ -63,7 +72,7 @@ impl Overlay {
             .context("parsing url for overlay")?
             .path_segments()
             .ok_or_else(|| format_err!("couldn't parse url: {}", self.url))?
-            .last()
+            .next_back()
             .ok_or_else(|| format_err!("url has no filename: {}", self.url))?
             .to_string();
 
@@ -73,13 +82,25 @@ impl Overlay {
         Ok(())
     }
 
+    /// Download from a mirror if one matches the start of the URL. The
+    /// longest matching prefix wins. The cache is still keyed by the
+    /// original URL.
+    pub fn use_mirrors(&mut self, mirrors: &BTreeMap<String, String>) {
+        self.mirror = mirrors
+            .iter()
+            .filter(|(prefix, _)| self.url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, mirror)| format!("{}{}", mirror, &self.url[prefix.len()..]));
+    }
+
     pub fn is_cached(&self) -> bool {
         self.cached.is_file()
     }
 
     pub fn download<F, G>(&self, length: F, update: G) -> Result<()> where
         F: FnOnce(u64), G: FnOnce(u64) + Copy {
-        let mut resp = reqwest::get(&self.url)?
+        let url = self.mirror.as_ref().unwrap_or(&self.url);
+        let mut resp = reqwest::get(url)?
             .error_for_status()?;
 
         length(resp.content_length().unwrap_or(0));
@@ -87,7 +108,7 @@ impl Overlay {
         let mut temp = NamedTempFile::new()?;
         let mut pw: ProgressWriter<_, G> = ProgressWriter{
             writer: &mut temp,
-            update: update,
+            update,
         };
 
         if let Err(e) = resp.copy_to(&mut pw) {
@@ -0,0 +1,52 @@
+use std::collections::{BTreeMap, HashMap};
+
+use failure::format_err;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_yaml::Value;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// A named set of variables that can be chosen with `pb init --preset`.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Preset {
+    /// Another preset whose variables this one starts from
+    #[serde(default)]
+    pub inherits: Option<String>,
+
+    /// A description of this preset
+    #[serde(default)]
+    pub help: Option<String>,
+
+    /// The variables this preset sets
+    #[serde(default)]
+    #[schemars(with = "HashMap<String, serde_json::Value>")]
+    pub vars: HashMap<String, Value>,
+}
+
+/// The presets `name` is built from, starting with the most basic one and
+/// ending with `name` itself.
+pub fn chain<'a>(presets: &'a BTreeMap<String, Preset>, name: &'a str) -> Result<Vec<(&'a str, &'a Preset)>> {
+    let mut chain: Vec<(&str, &Preset)> = Vec::new();
+    let mut next = Some(name);
+
+    while let Some(name) = next {
+        if chain.iter().any(|(n, _)| *n == name) {
+            return Err(format_err!("preset '{}' inherits from itself", name));
+        }
+
+        let preset = presets.get(name).ok_or_else(|| match chain.last() {
+            Some((child, _)) => format_err!("preset '{}' inherits from unknown preset '{}'", child, name),
+            None => match super::variable::suggest(name, presets.keys()) {
+                Some(s) => format_err!("Unknown preset '{}', did you mean '{}'?", name, s),
+                None => format_err!("Unknown preset '{}'", name),
+            },
+        })?;
+
+        chain.push((name, preset));
+        next = preset.inherits.as_deref();
+    }
+
+    chain.reverse();
+    Ok(chain)
+}
@@ -0,0 +1,236 @@
+//! Configure-time checks, run by `pb init`. Each probe runs a command and
+//! turns its result into a value that templates and `enabled_if`
+//! expressions can use like a variable.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use failure::{format_err, ResultExt};
+use log::debug;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use super::state::content_hash;
+use super::variable::scalar_string;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// What a probe's value is.
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeKind {
+    /// Whether the command succeeded
+    #[default]
+    Success,
+
+    /// What the command printed, or not set if it failed
+    Output,
+}
+
+/// A command to run at init time, set in the project's `probes` section.
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct Probe {
+    /// The program and its arguments. `${name}` is replaced with the value
+    /// of a variable; an argument that is only `${name}` of a list variable
+    /// becomes one argument per item.
+    pub command: Vec<String>,
+
+    /// What the probe's value is: "success" or "output"
+    #[serde(default)]
+    pub result: ProbeKind,
+
+    /// For output probes, a regular expression to find the value in the
+    /// output. The first capture group is used if there is one, otherwise
+    /// the whole match.
+    #[serde(default)]
+    pub regex: Option<String>,
+
+    /// Text to give the command on its standard input
+    #[serde(default)]
+    pub input: Option<String>,
+}
+
+/// A probe's value, and what it was computed from.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProbeRecord {
+    /// Identifies the probe definition, command line and program it ran
+    pub key: String,
+
+    pub value: Value,
+}
+
+/// Find a program the way the shell would: as a path if it has a slash,
+/// otherwise by searching `PATH`.
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return if path.is_file() { Some(path) } else { None };
+    }
+
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Replace each `${name}` in `arg` with the value of a variable.
+pub fn substitute(arg: &str, vars: &HashMap<String, Value>) -> Result<Vec<String>> {
+    let var = Regex::new(r"\$\{([A-Za-z0-9_.]+)\}").unwrap();
+    let lookup = |name: &str| {
+        vars.get(name).ok_or_else(|| format_err!("unknown variable '{}'", name))
+    };
+
+    // A lone list variable expands to one argument per item
+    if let Some(caps) = var.captures(arg) {
+        if caps[0].len() == arg.len() {
+            if let Value::Sequence(items) = lookup(&caps[1])? {
+                return Ok(items.iter().filter_map(scalar_string).collect());
+            }
+        }
+    }
+
+    let mut error = None;
+    let result = var.replace_all(arg, |caps: &regex::Captures| {
+        match lookup(&caps[1]).map(|v| scalar_string(v).unwrap_or_default()) {
+            Ok(v) => v,
+            Err(e) => {
+                error = Some(e);
+                String::new()
+            },
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(vec![result.into_owned()]),
+    }
+}
+
+/// Something that changes whenever the program at `path` does.
+fn program_stamp(path: &Path) -> String {
+    match std::fs::metadata(path) {
+        Ok(meta) => {
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!("{}:{}:{}", path.display(), meta.len(), modified)
+        },
+        Err(_) => String::new(),
+    }
+}
+
+impl Probe {
+    /// The command line with variables substituted in.
+    fn command_line(&self, vars: &HashMap<String, Value>) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        for arg in &self.command {
+            args.extend(substitute(arg, vars)?);
+        }
+
+        if args.is_empty() {
+            return Err(format_err!("probe has no command"));
+        }
+        Ok(args)
+    }
+
+    /// A key that changes if the probe, its command line or the program it
+    /// runs changes, and is the same for every build of Bonnibel.
+    fn cache_key(&self, args: &[String], program: Option<&Path>) -> Result<String> {
+        let stamp = program.map(program_stamp);
+        let contents = serde_json::to_vec(&(self, args, stamp)).context("hashing probe")?;
+        Ok(content_hash(&contents))
+    }
+
+    fn run(&self, args: &[String], program: &Path) -> Result<Value> {
+        let mut child = Command::new(program)
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|_| format!("running {}", args[0]))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // The command may well exit without reading its input
+            let _ = stdin.write_all(self.input.as_deref().unwrap_or("").as_bytes());
+        }
+
+        let output = child.wait_with_output().context("waiting for probe")?;
+        debug!("Probe {:?} exited with {}", args, output.status);
+
+        match self.result {
+            ProbeKind::Success => Ok(Value::Bool(output.status.success())),
+            ProbeKind::Output if !output.status.success() => Ok(Value::Null),
+            ProbeKind::Output => {
+                // Plenty of tools print their version on stderr
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+                let text = match &self.regex {
+                    None => text.trim().to_string(),
+                    Some(re) => {
+                        let re = Regex::new(re).with_context(|_| format!("parsing regex '{}'", re))?;
+                        match re.captures(&text) {
+                            Some(caps) => caps.get(1).or_else(|| caps.get(0)).unwrap().as_str().to_string(),
+                            None => return Ok(Value::Null),
+                        }
+                    },
+                };
+                Ok(Value::String(text))
+            },
+        }
+    }
+
+    /// Run the probe, or reuse `cached` if nothing it depends on has
+    /// changed. Returns the record and whether it came from the cache.
+    pub fn evaluate(
+        &self,
+        vars: &HashMap<String, Value>,
+        cached: Option<&ProbeRecord>,
+    ) -> Result<(ProbeRecord, bool)> {
+        let args = self.command_line(vars)?;
+        let program = find_program(&args[0]);
+        let key = self.cache_key(&args, program.as_deref())?;
+
+        if let Some(cached) = cached {
+            if cached.key == key {
+                return Ok((cached.clone(), true));
+            }
+        }
+
+        let value = match &program {
+            Some(program) => self.run(&args, program)?,
+            None => match self.result {
+                ProbeKind::Success => Value::Bool(false),
+                ProbeKind::Output => Value::Null,
+            },
+        };
+
+        Ok((ProbeRecord { key, value }, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_key_is_stable() {
+        let probe: Probe = serde_yaml::from_str("command: [cc, --version]\nresult: output\n").unwrap();
+        let args = ["cc".to_string(), "--version".to_string()];
+        let key = probe.cache_key(&args, None).unwrap();
+        // The same everywhere, as it only hashes the probe's serialized form
+        assert_eq!(key, content_hash(
+            br#"[{"command":["cc","--version"],"result":"output","regex":null,"input":null},["cc","--version"],null]"#));
+        assert_eq!(key, "d04e1eadf37230e6");
+        assert_ne!(probe.cache_key(&args[..1], None).unwrap(), key);
+    }
+}
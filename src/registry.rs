@@ -0,0 +1,61 @@
+//! The build directories `pb init` has set up, kept per user so that
+//! `pb generate --all` and `pb build --all` can find every one of a
+//! project's build directories, wherever they are.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use failure::ResultExt;
+
+use super::lock::{write_atomic, BuildLock};
+use super::State;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+pub const REGISTRY_FILE_NAME: &str = "build_dirs.yaml";
+
+/// Build directories keyed by the project file they were initialized from.
+type Registry = BTreeMap<PathBuf, BTreeSet<PathBuf>>;
+
+fn load(path: &Path) -> Result<Registry> {
+    if !path.is_file() {
+        return Ok(Registry::new());
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .with_context(|_| format!("reading {}", path.display()))?;
+    Ok(serde_yaml::from_str(&contents).with_context(|_| format!("parsing {}", path.display()))?)
+}
+
+/// Whether `build_dir` is still a build directory of `project`.
+fn belongs_to(build_dir: &Path, project: &Path) -> bool {
+    State::exists(build_dir) &&
+        matches!(State::load(build_dir), Ok(State { project: Some(p), .. }) if p == project)
+}
+
+/// Remember `build_dir` as one of `project`'s build directories, and
+/// forget any that no longer are.
+pub fn register_build_dir(dirs: &ProjectDirs, project: &Path, build_dir: &Path) -> Result<()> {
+    let _lock = BuildLock::acquire(dirs.data_dir())?;
+    let path = dirs.data_dir().join(REGISTRY_FILE_NAME);
+
+    let mut registry = load(&path)?;
+    registry.entry(project.to_path_buf()).or_default().insert(build_dir.to_path_buf());
+    for (project, build_dirs) in registry.iter_mut() {
+        build_dirs.retain(|dir| belongs_to(dir, project));
+    }
+    registry.retain(|_, build_dirs| !build_dirs.is_empty());
+
+    let contents = serde_yaml::to_string(&registry).context("writing build directory list")? + "\n";
+    write_atomic(&path, contents.as_bytes())
+}
+
+/// Every known build directory of `project`, sorted.
+pub fn known_build_dirs(dirs: &ProjectDirs, project: &Path) -> Result<Vec<PathBuf>> {
+    let registry = load(&dirs.data_dir().join(REGISTRY_FILE_NAME))?;
+    Ok(registry
+        .get(project)
+        .map(|build_dirs| build_dirs.iter().filter(|d| belongs_to(d, project)).cloned().collect())
+        .unwrap_or_default())
+}
@@ -0,0 +1,87 @@
+//! Starter projects written by `pb new`.
+
+use std::path::{Path, PathBuf};
+
+use failure::{format_err, ResultExt};
+
+use super::{find_config, Header};
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+type Files = &'static [(&'static str, &'static str)];
+
+const TEMPLATES: Files = &[
+    ("templates/build.ninja.j2", include_str!("scaffold/templates/build.ninja.j2")),
+    ("templates/lib.default.j2", include_str!("scaffold/templates/lib.default.j2")),
+    ("templates/exe.default.j2", include_str!("scaffold/templates/exe.default.j2")),
+];
+
+const C: Files = &[
+    ("modules.yaml", include_str!("scaffold/c/modules.yaml")),
+    ("templates/target.native.j2", include_str!("scaffold/c/target.native.j2")),
+    ("src/greeting/greeting.h", include_str!("scaffold/c/greeting.h")),
+    ("src/greeting/greeting.c", include_str!("scaffold/c/greeting.c")),
+    ("src/hello/main.c", include_str!("scaffold/c/main.c")),
+];
+
+const CPP: Files = &[
+    ("modules.yaml", include_str!("scaffold/cpp/modules.yaml")),
+    ("templates/target.native.j2", include_str!("scaffold/cpp/target.native.j2")),
+    ("src/greeting/greeting.h", include_str!("scaffold/cpp/greeting.h")),
+    ("src/greeting/greeting.cpp", include_str!("scaffold/cpp/greeting.cpp")),
+    ("src/hello/main.cpp", include_str!("scaffold/cpp/main.cpp")),
+];
+
+const ASM_KERNEL: Files = &[
+    ("modules.yaml", include_str!("scaffold/asm-kernel/modules.yaml")),
+    ("templates/target.kernel.j2", include_str!("scaffold/asm-kernel/target.kernel.j2")),
+    ("src/kernel/boot.S", include_str!("scaffold/asm-kernel/boot.S")),
+    ("src/kernel/kernel.ld", include_str!("scaffold/asm-kernel/kernel.ld")),
+];
+
+/// The names of the available starter projects.
+pub const SCAFFOLDS: &[&str] = &["c", "cpp", "asm-kernel"];
+
+/// Write a starter project into `dir`, returning the files created.
+pub fn scaffold(dir: &Path, kind: &str) -> Result<Vec<PathBuf>> {
+    let files = match kind {
+        "c" => C,
+        "cpp" => CPP,
+        "asm-kernel" => ASM_KERNEL,
+        _ => return Err(format_err!("Unknown project template '{}', expected one of: {}",
+                                    kind, SCAFFOLDS.join(", "))),
+    };
+
+    if let Some(existing) = find_config(dir) {
+        return Err(format_err!("{:?} already exists", existing));
+    }
+
+    let dir = std::fs::canonicalize(dir).context("finding project directory")?;
+    let name = dir.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("project");
+    let header = Header::current();
+
+    // Check every file before writing any, so nothing is left half done
+    for (path, _) in files.iter().chain(TEMPLATES.iter()) {
+        let path = dir.join(path);
+        if path.exists() {
+            return Err(format_err!("{:?} already exists", path));
+        }
+    }
+
+    let mut created = Vec::new();
+    for (path, contents) in files.iter().chain(TEMPLATES.iter()) {
+        let path = dir.join(path);
+        let contents = contents
+            .replace("@NAME@", name)
+            .replace("@SCHEMA@", &header.schema.to_string())
+            .replace("@REQUIRES@", header.requires.as_deref().unwrap_or("*"));
+
+        std::fs::create_dir_all(path.parent().unwrap()).context("creating project directory")?;
+        std::fs::write(&path, contents).with_context(|_| format!("writing {:?}", path))?;
+        created.push(path);
+    }
+
+    Ok(created)
+}
@@ -0,0 +1,7 @@
+#include <iostream>
+#include "greeting.h"
+
+void greet(const std::string &name)
+{
+    std::cout << "Hello, " << name << "!" << std::endl;
+}
@@ -0,0 +1,4 @@
+#pragma once
+#include <string>
+
+void greet(const std::string &name);
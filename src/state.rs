@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use super::lock::write_atomic;
+use super::{find_config, DetectedToolchain, Origin, ProbeRecord};
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+pub const STATE_FILE_NAME: &str = ".bonnibel_vars";
+
+/// A hash of a file's contents, to notice when it changes. This is 64-bit
+/// FNV-1a, which unlike the standard library's hasher gives the same result
+/// in every version of Bonnibel.
+pub fn content_hash(contents: &[u8]) -> String {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in contents {
+        h ^= u64::from(*byte);
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", h)
+}
+
+/// What a build directory remembers between runs of `pb`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    /// The version of Bonnibel that last wrote this state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bonnibel: Option<String>,
+
+    /// The project file this build directory was initialized from
+    #[serde(default)]
+    pub project: Option<PathBuf>,
+
+    /// A hash of the project file, and of each subproject's, when the
+    /// build files were last generated
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_hashes: BTreeMap<PathBuf, String>,
+
+    /// A hash of each vars file given to `pb init`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars_files: BTreeMap<PathBuf, String>,
+
+    pub vars: BTreeMap<String, Value>,
+
+    /// Where each variable's value came from
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub origins: BTreeMap<String, Origin>,
+
+    /// The preset chosen at init time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+
+    /// The results of the project's probes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub probes: BTreeMap<String, ProbeRecord>,
+
+    /// The toolchains found for the active targets
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub toolchains: BTreeMap<String, DetectedToolchain>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StateFile {
+    Current(State),
+
+    /// Older versions stored only the var map
+    Legacy(HashMap<String, String>),
+}
+
+impl State {
+    pub fn path(build_dir: &Path) -> PathBuf {
+        build_dir.join(STATE_FILE_NAME)
+    }
+
+    pub fn exists(build_dir: &Path) -> bool {
+        State::path(build_dir).is_file()
+    }
+
+    pub fn load(build_dir: &Path) -> Result<State> {
+        let contents = std::fs::read_to_string(State::path(build_dir)).context("reading state file")?;
+        let file: StateFile = serde_yaml::from_str(&contents).context("parsing state file")?;
+
+        Ok(match file {
+            StateFile::Current(state) => state,
+            StateFile::Legacy(vars) => State {
+                bonnibel: None,
+                project: None,
+                config_hashes: BTreeMap::new(),
+                vars_files: BTreeMap::new(),
+                origins: BTreeMap::new(),
+                preset: None,
+                probes: BTreeMap::new(),
+                toolchains: BTreeMap::new(),
+                vars: vars.into_iter().map(|(k, v)| (k, Value::String(v))).collect(),
+            },
+        })
+    }
+
+    /// Save the state, replacing any existing state file atomically.
+    pub fn save(&self, build_dir: &Path) -> Result<()> {
+        let contents = serde_yaml::to_string(self).context("writing state file")? + "\n";
+        write_atomic(&State::path(build_dir), contents.as_bytes())
+    }
+}
+
+/// Where a project was found, and the build directory it was found from
+/// if any.
+#[derive(Debug)]
+pub struct Discovered {
+    pub config_file: PathBuf,
+    pub build_dir: Option<PathBuf>,
+}
+
+/// Find the project by walking up from `start`, git-style. A build
+/// directory that records its project file is used directly; otherwise the
+/// first directory containing a project file wins.
+pub fn discover(start: &Path) -> Option<Discovered> {
+    for dir in start.ancestors() {
+        if State::exists(dir) {
+            if let Ok(State { project: Some(config_file), .. }) = State::load(dir) {
+                return Some(Discovered { config_file, build_dir: Some(dir.to_path_buf()) });
+            }
+        }
+
+        if let Some(config_file) = find_config(dir) {
+            return Some(Discovered { config_file, build_dir: None });
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::content_hash;
+
+    #[test]
+    fn content_hash_is_fnv1a() {
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c");
+        assert_eq!(content_hash(b"foobar"), "85944171f73967e8");
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::{Path, PathBuf};
+
+use failure::format_err;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::find_config;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Another Bonnibel project whose modules are loaded into this one under
+/// the namespace `name`. Its overlays, variable declarations, probes and
+/// toolchains are added to this project's, and its `vars` are used for any
+/// this project doesn't set. It may not set target options, presets or a
+/// config header.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Subproject {
+    /// The namespace the subproject's modules are loaded into
+    pub name: String,
+
+    /// The subproject's directory, relative to this project's root
+    pub path: PathBuf,
+
+    /// The subproject's modules file, relative to `path`
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+impl Subproject {
+    pub fn config_file(&self, root: &Path) -> Result<PathBuf> {
+        let dir = root.join(&self.path);
+        match &self.file {
+            Some(file) => Ok(dir.join(file)),
+            None => find_config(&dir)
+                .ok_or_else(|| format_err!("no project file found in {:?}", dir)),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use failure::format_err;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Options for a target, set in the project's `targets` section.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct TargetOptions {
+    /// The template file to render this target with
+    #[serde(default)]
+    pub template: Option<String>,
+
+    /// The toolchain from the project's `toolchains` section to build
+    /// this target with
+    #[serde(default)]
+    pub toolchain: Option<String>,
+}
+
+/// The template chosen to render a module or target, and why.
+#[derive(Debug)]
+pub struct TemplateChoice {
+    pub path: PathBuf,
+    pub file: String,
+    pub reason: String,
+}
+
+impl fmt::Display for TemplateChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.file, self.reason)
+    }
+}
+
+/// Find the template to use for `name` of the given `kind`. An explicit
+/// template always wins, otherwise the first of these that exists is used:
+///
+///   <kind>.<name>.<target>.j2
+///   <kind>.<name>.j2
+///   <kind>.<target>.j2
+///   <kind>.default.j2
+///
+/// A library's target is only known when exactly one target uses it. One
+/// used by several targets is rendered once for all of them, so only the
+/// lookups without a target apply to it.
+pub fn find_template(
+    root: &Path,
+    name: &str,
+    kind: &str,
+    target: Option<&str>,
+    explicit: Option<&str>,
+) -> Result<TemplateChoice> {
+    if let Some(explicit) = explicit {
+        let mut candidates = vec![explicit.to_string()];
+        if !explicit.ends_with(".j2") {
+            candidates.push(format!("{}.j2", explicit));
+        }
+
+        return candidates
+            .into_iter()
+            .map(|file| (root.join(&file), file))
+            .find(|(path, _)| path.exists())
+            .map(|(path, file)| TemplateChoice {
+                path,
+                file,
+                reason: "explicit `template` key".to_string(),
+            })
+            .ok_or_else(|| format_err!("Missing template '{}' chosen for '{}'.", explicit, name));
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(target) = target {
+        candidates.push((
+            format!("{}.{}.{}.j2", kind, name, target),
+            format!("matched name '{}' and target '{}'", name, target)));
+    }
+
+    candidates.push((format!("{}.{}.j2", kind, name), format!("matched name '{}'", name)));
+
+    if let Some(target) = target {
+        candidates.push((format!("{}.{}.j2", kind, target), format!("matched target '{}'", target)));
+    }
+
+    candidates.push((format!("{}.default.j2", kind), format!("default for kind '{}'", kind)));
+
+    candidates
+        .into_iter()
+        .map(|(file, reason)| (root.join(&file), file, reason))
+        .find(|(path, _, _)| path.exists())
+        .map(|(path, file, reason)| TemplateChoice { path, file, reason })
+        .ok_or_else(|| format_err!("Missing template for module '{}'.", name))
+}
@@ -0,0 +1,240 @@
+//! Toolchains bound to targets. `pb init` finds each tool on `PATH`,
+//! checks its version, and records what it found in the build directory
+//! for the target and module templates to use.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use failure::{format_err, ResultExt};
+use regex::Regex;
+use schemars::JsonSchema;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use super::probe::{find_program, substitute};
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+fn default_version_flag() -> String {
+    "--version".to_string()
+}
+
+/// A tool to find, either just a program name or a full description.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Tool {
+    Program(String),
+    Spec(ToolSpec),
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ToolSpec {
+    /// Program names to try, in order. The first one found, and of the
+    /// right version, is used.
+    pub programs: Vec<String>,
+
+    /// A semver requirement on the tool's version, eg ">=10"
+    #[serde(default)]
+    pub version: Option<String>,
+
+    /// The argument that makes the tool print its version
+    #[serde(default = "default_version_flag")]
+    pub version_flag: String,
+}
+
+/// A set of tools used to build a target, set in the project's
+/// `toolchains` section. Program names, flags and the sysroot may use
+/// `${name}` to refer to a variable.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Toolchain {
+    /// The C compiler
+    #[serde(default)]
+    pub cc: Option<Tool>,
+
+    /// The C++ compiler
+    #[serde(default)]
+    pub cxx: Option<Tool>,
+
+    /// The assembler
+    #[serde(default, rename = "as")]
+    pub asm: Option<Tool>,
+
+    /// The linker
+    #[serde(default)]
+    pub ld: Option<Tool>,
+
+    /// The archiver
+    #[serde(default)]
+    pub ar: Option<Tool>,
+
+    /// The object file converter, eg for making a flat binary
+    #[serde(default)]
+    pub objcopy: Option<Tool>,
+
+    /// Flags for the templates to use, keyed by what they are for, eg
+    /// `cflags` or `ldflags`
+    #[serde(default)]
+    pub flags: BTreeMap<String, Vec<String>>,
+
+    /// The system root to build against, relative to the project root
+    #[serde(default)]
+    pub sysroot: Option<String>,
+}
+
+/// What `pb init` found for a toolchain. In templates, `toolchain.cc` and
+/// so on are the paths of the tools.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct DetectedToolchain {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub tools: BTreeMap<String, PathBuf>,
+
+    /// The versions of tools that have a version requirement
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub versions: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub flags: BTreeMap<String, Vec<String>>,
+
+    #[serde(default)]
+    pub sysroot: Option<PathBuf>,
+}
+
+impl Tool {
+    fn spec(&self) -> (Vec<&str>, Option<&str>, &str) {
+        match self {
+            Tool::Program(p) => (vec![p.as_str()], None, "--version"),
+            Tool::Spec(s) => (
+                s.programs.iter().map(String::as_str).collect(),
+                s.version.as_deref(),
+                &s.version_flag),
+        }
+    }
+}
+
+/// Ask a program for its version, taking the first thing in its output
+/// that looks like one.
+fn query_version(program: &Path, flag: &str) -> Result<Option<Version>> {
+    let output = Command::new(program)
+        .arg(flag)
+        .output()
+        .with_context(|_| format!("running {}", program.display()))?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    let re = Regex::new(r"(\d+)\.(\d+)(?:\.(\d+))?").unwrap();
+    Ok(re.captures(&text).map(|caps| {
+        let part = |i| caps.get(i).map(|m| m.as_str().parse().unwrap_or(0)).unwrap_or(0);
+        Version::new(part(1), part(2), part(3))
+    }))
+}
+
+fn single(arg: &str, vars: &HashMap<String, Value>) -> Result<String> {
+    match substitute(arg, vars)?.as_slice() {
+        [one] => Ok(one.to_string()),
+        _ => Err(format_err!("'{}' must be a single value", arg)),
+    }
+}
+
+/// Find the first of a tool's programs that exists and satisfies its
+/// version requirement.
+fn detect_tool(tool: &Tool, vars: &HashMap<String, Value>) -> Result<(PathBuf, Option<Version>)> {
+    let (programs, version, flag) = tool.spec();
+    let req = version
+        .map(|v| VersionReq::parse(v).with_context(|_| format!("parsing version '{}'", v)))
+        .transpose()?;
+
+    let mut tried = Vec::new();
+    let mut rejected = Vec::new();
+    for program in programs.iter() {
+        let program = single(program, vars)?;
+        tried.push(program.to_string());
+        let path = match find_program(&program) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let req = match &req {
+            Some(req) => req,
+            None => return Ok((path, None)),
+        };
+
+        match query_version(&path, flag)? {
+            Some(v) if req.matches(&v) => return Ok((path, Some(v))),
+            Some(v) => rejected.push(format!("{} is version {}", path.display(), v)),
+            None => rejected.push(format!("{} printed no version", path.display())),
+        }
+    }
+
+    let tried = tried.join(", ");
+    if rejected.is_empty() {
+        Err(format_err!("none of {} found on PATH", tried))
+    } else {
+        Err(format_err!(
+            "none of {} is version {}: {}", tried, version.unwrap_or(""), rejected.join(", ")))
+    }
+}
+
+impl Toolchain {
+    fn tools(&self) -> Vec<(&'static str, &Tool)> {
+        vec![
+            ("cc", &self.cc),
+            ("cxx", &self.cxx),
+            ("as", &self.asm),
+            ("ld", &self.ld),
+            ("ar", &self.ar),
+            ("objcopy", &self.objcopy),
+        ]
+        .into_iter()
+        .filter_map(|(kind, tool)| tool.as_ref().map(|t| (kind, t)))
+        .collect()
+    }
+
+    /// Check what can be checked without running anything.
+    pub fn check(&self) -> Result<()> {
+        for (kind, tool) in self.tools() {
+            let (programs, version, _) = tool.spec();
+            if programs.is_empty() {
+                return Err(format_err!("{} lists no programs", kind));
+            }
+            if let Some(v) = version {
+                VersionReq::parse(v).with_context(|_| format!("parsing {} version '{}'", kind, v))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find every tool in the toolchain.
+    pub fn detect(&self, name: &str, root: &Path, vars: &HashMap<String, Value>) -> Result<DetectedToolchain> {
+        let mut found = DetectedToolchain { name: name.to_string(), ..Default::default() };
+
+        for (kind, tool) in self.tools() {
+            let (path, version) = detect_tool(tool, vars).with_context(|_| format!("finding {}", kind))?;
+            if let Some(version) = version {
+                found.versions.insert(kind.to_string(), version.to_string());
+            }
+            found.tools.insert(kind.to_string(), path);
+        }
+
+        for (kind, flags) in self.flags.iter() {
+            let mut args = Vec::new();
+            for flag in flags {
+                args.extend(substitute(flag, vars)?);
+            }
+            found.flags.insert(kind.to_string(), args);
+        }
+
+        found.sysroot = match &self.sysroot {
+            Some(sysroot) => Some(root.join(single(sysroot, vars)?)),
+            None => None,
+        };
+
+        Ok(found)
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use directories::ProjectDirs;
+use failure::ResultExt;
+use serde::Deserialize;
+use serde_yaml::Value;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+pub const USER_CONFIG_FILE_NAME: &str = "config.yaml";
+
+/// Per-user settings, read from `config.yaml` in the user's Bonnibel config
+/// directory (eg `~/.config/bonnibel/config.yaml`). Everything here is a
+/// default: command line options, and variables from any other source,
+/// take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// The download cache for `pb sync`
+    #[serde(default)]
+    pub cache: Option<PathBuf>,
+
+    /// The build directory to use, relative to the project root
+    #[serde(default)]
+    pub build_dir: Option<PathBuf>,
+
+    /// URL prefixes to download overlays from instead, keyed by the
+    /// prefix they replace
+    #[serde(default)]
+    pub mirrors: BTreeMap<String, String>,
+
+    /// Default values for variables, used by any project that declares them
+    #[serde(default)]
+    pub vars: HashMap<String, Value>,
+
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+impl UserConfig {
+    pub fn path(dirs: &ProjectDirs) -> PathBuf {
+        dirs.config_dir().join(USER_CONFIG_FILE_NAME)
+    }
+
+    /// Load the user's config file, or an empty config if there is none.
+    pub fn load(dirs: &ProjectDirs) -> Result<UserConfig> {
+        let path = UserConfig::path(dirs);
+        if !path.is_file() {
+            return Ok(UserConfig { path, ..Default::default() });
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|_| format!("reading user config {}", path.display()))?;
+        let mut config: UserConfig = serde_yaml::from_str(&contents)
+            .with_context(|_| format!("parsing user config {}", path.display()))?;
+        config.path = path;
+        Ok(config)
+    }
+}
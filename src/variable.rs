@@ -0,0 +1,357 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use failure::format_err;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// The type of a declared variable.
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VarType {
+    #[default]
+    String,
+    Bool,
+    Int,
+    Path,
+    Enum,
+    List,
+    Map,
+}
+
+impl fmt::Display for VarType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            VarType::String => "string",
+            VarType::Bool => "bool",
+            VarType::Int => "int",
+            VarType::Path => "path",
+            VarType::Enum => "enum",
+            VarType::List => "list",
+            VarType::Map => "map",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A variable declared in the project's `variables` section.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+pub struct Variable {
+    /// The type of value this variable holds
+    #[serde(default, rename = "type")]
+    pub kind: VarType,
+
+    /// The value used if none is given at init time
+    #[serde(default)]
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub default: Option<Value>,
+
+    /// The values this variable is allowed to take. For lists, the values
+    /// each item is allowed to take.
+    #[serde(default)]
+    pub choices: Vec<String>,
+
+    /// A description of what this variable controls
+    #[serde(default)]
+    pub help: Option<String>,
+
+    /// An environment variable to read this variable from at init time,
+    /// eg "CC"
+    #[serde(default)]
+    pub env: Option<String>,
+}
+
+/// Where a variable's value came from.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Origin {
+    /// The variable's declared default
+    Default,
+
+    /// The project file's `vars` section
+    Project,
+
+    /// The user's config file
+    User,
+
+    /// The named environment variable
+    Env(String),
+
+    /// The named preset
+    Preset(String),
+
+    /// The named vars file
+    File(String),
+
+    /// The command line
+    Cli,
+
+    /// `pb menuconfig`
+    Menu,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::Project => write!(f, "project"),
+            Origin::User => write!(f, "user config"),
+            Origin::Env(name) => write!(f, "env ${}", name),
+            Origin::Preset(name) => write!(f, "preset {}", name),
+            Origin::File(path) => write!(f, "file {}", path),
+            Origin::Cli => write!(f, "command line"),
+            Origin::Menu => write!(f, "menuconfig"),
+        }
+    }
+}
+
+impl From<Origin> for String {
+    fn from(origin: Origin) -> String {
+        match origin {
+            Origin::Default => "default".to_string(),
+            Origin::Project => "project".to_string(),
+            Origin::User => "user".to_string(),
+            Origin::Env(name) => format!("env:{}", name),
+            Origin::Preset(name) => format!("preset:{}", name),
+            Origin::File(path) => format!("file:{}", path),
+            Origin::Cli => "cli".to_string(),
+            Origin::Menu => "menu".to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for Origin {
+    type Error = failure::Error;
+
+    fn try_from(s: String) -> Result<Origin> {
+        Ok(match s.as_str() {
+            "default" => Origin::Default,
+            "project" => Origin::Project,
+            "user" => Origin::User,
+            "cli" => Origin::Cli,
+            "menu" => Origin::Menu,
+            _ => match s.split_once(':') {
+                Some(("env", name)) => Origin::Env(name.to_string()),
+                Some(("preset", name)) => Origin::Preset(name.to_string()),
+                Some(("file", path)) => Origin::File(path.to_string()),
+                _ => return Err(format_err!("unknown variable origin '{}'", s)),
+            },
+        })
+    }
+}
+
+/// The string form of a scalar value, as it would be given on the command
+/// line.
+pub fn scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Format a value for display: scalars as-is, lists and maps in YAML flow
+/// style.
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Sequence(items) => {
+            let items: Vec<String> = items.iter().map(display).collect();
+            format!("[{}]", items.join(", "))
+        },
+        Value::Mapping(map) => {
+            let items: Vec<String> = map
+                .iter()
+                .map(|(k, v)| format!("{}: {}", display(k), display(v)))
+                .collect();
+            format!("{{{}}}", items.join(", "))
+        },
+        _ => scalar_string(value).unwrap_or_default(),
+    }
+}
+
+impl Variable {
+    /// The value of this variable's environment variable, if it has one
+    /// and it is set. Lists are split on whitespace, like `CFLAGS`.
+    pub fn from_env(&self) -> Option<Value> {
+        let value = std::env::var(self.env.as_ref()?).ok()?;
+        Some(match self.kind {
+            VarType::List => Value::Sequence(
+                value.split_whitespace().map(|v| Value::String(v.to_string())).collect()),
+            _ => Value::String(value),
+        })
+    }
+
+    /// Make sure the declaration itself makes sense.
+    pub fn check_declaration(&self, name: &str) -> Result<()> {
+        if self.kind == VarType::Enum && self.choices.is_empty() {
+            return Err(format_err!("enum variable '{}' has no choices", name));
+        }
+
+        if let Some(default) = &self.default {
+            self.check(name, default.clone())
+                .map_err(|e| format_err!("bad default for variable '{}': {}", name, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn check_choice(&self, name: &str, value: &str) -> Result<()> {
+        if !self.choices.is_empty() && !self.choices.iter().any(|c| c == value) {
+            return Err(format_err!(
+                "'{}' is not a valid value for variable '{}', expected one of: {}",
+                value, name, self.choices.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// Check that `value` is valid for this variable, returning it as its
+    /// declared type. Scalars given as strings are converted.
+    pub fn check(&self, name: &str, value: Value) -> Result<Value> {
+        let value = match (self.kind, value) {
+            (VarType::List, Value::Sequence(items)) => {
+                for item in &items {
+                    let item = scalar_string(item).ok_or_else(|| format_err!(
+                        "items of list variable '{}' must be single values", name))?;
+                    self.check_choice(name, &item)?;
+                }
+                return Ok(Value::Sequence(items));
+            },
+            (VarType::List, _) => return Err(format_err!(
+                "variable '{}' is a list, set it with `{}:=[...]` or `{}+=value`", name, name, name)),
+
+            (VarType::Map, Value::Mapping(map)) => return Ok(Value::Mapping(map)),
+            (VarType::Map, _) => return Err(format_err!(
+                "variable '{}' is a map, set it with `{}:={{key: value}}`", name, name)),
+
+            (kind, value) => {
+                let s = scalar_string(&value).ok_or_else(|| format_err!(
+                    "variable '{}' holds a single {} value, but was given a list or map", name, kind))?;
+
+                match kind {
+                    VarType::Bool => match s.to_lowercase().as_str() {
+                        "true" | "yes" | "on" | "1" => Value::Bool(true),
+                        "false" | "no" | "off" | "0" | "" => Value::Bool(false),
+                        _ => return Err(format_err!(
+                            "variable '{}' is a bool, but was given '{}'", name, s)),
+                    },
+
+                    VarType::Int => s
+                        .parse::<i64>()
+                        .map(Value::from)
+                        .map_err(|_| format_err!("variable '{}' is an int, but was given '{}'", name, s))?,
+
+                    VarType::Path if s.is_empty() =>
+                        return Err(format_err!("variable '{}' is a path, but was given nothing", name)),
+
+                    _ => Value::String(s),
+                }
+            },
+        };
+
+        self.check_choice(name, &scalar_string(&value).unwrap_or_default())?;
+        Ok(value)
+    }
+}
+
+/// A change to a variable given on the command line.
+#[derive(Debug)]
+pub enum Assignment {
+    /// `name=value`, a plain string
+    Set(String),
+
+    /// `name:=value`, a YAML value
+    SetValue(Value),
+
+    /// `name+=value`, append to a list
+    Append(String),
+
+    /// `name-=value`, remove from a list
+    Remove(String),
+}
+
+/// Parse a `name=value`, `name:=value`, `name+=value` or `name-=value`
+/// argument.
+pub fn parse_assignment(arg: &str) -> Result<(String, Assignment)> {
+    let index = arg
+        .find('=')
+        .ok_or_else(|| format_err!("Variable {} does not parse", arg))?;
+    let (name, value) = (&arg[..index], arg[index + 1 ..].to_string());
+
+    let (name, assignment) = match name.chars().last() {
+        Some('+') => (&name[..name.len() - 1], Assignment::Append(value)),
+        Some('-') => (&name[..name.len() - 1], Assignment::Remove(value)),
+        Some(':') => {
+            let value = serde_yaml::from_str(&value)
+                .map_err(|e| format_err!("Value for variable {} does not parse: {}", name, e))?;
+            (&name[..name.len() - 1], Assignment::SetValue(value))
+        },
+        _ => (name, Assignment::Set(value)),
+    };
+
+    if name.is_empty() {
+        return Err(format_err!("Variable {} does not parse", arg));
+    }
+    Ok((name.to_string(), assignment))
+}
+
+impl Assignment {
+    /// Apply this change to the variable's current value, if any.
+    pub fn apply(self, name: &str, current: Option<Value>) -> Result<Value> {
+        let list = |current: Option<Value>| match current {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::Sequence(items)) => Ok(items),
+            Some(_) => Err(format_err!("variable '{}' is not a list", name)),
+        };
+
+        Ok(match self {
+            Assignment::Set(value) => Value::String(value),
+            Assignment::SetValue(value) => value,
+            Assignment::Append(value) => {
+                let mut items = list(current)?;
+                items.push(Value::String(value));
+                Value::Sequence(items)
+            },
+            Assignment::Remove(value) => {
+                let mut items = list(current)?;
+                items.retain(|i| scalar_string(i).as_deref() != Some(value.as_str()));
+                Value::Sequence(items)
+            },
+        })
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// The name from `names` closest to `name`, if any is close enough to
+/// have been meant.
+pub fn suggest<'a, I>(name: &str, names: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    names
+        .into_iter()
+        .map(|n| (edit_distance(name, n), n))
+        .filter(|(d, n)| *d <= 2.max(n.len() / 3))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, n)| n.as_str())
+}
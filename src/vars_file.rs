@@ -0,0 +1,84 @@
+//! Files of variables given to `pb init --vars-file`. A file ending in
+//! `.yaml`, `.yml` or `.json` holds a mapping of variable names to values;
+//! anything else holds one `name=value` assignment per line, with the same
+//! syntax as on the command line. Blank lines and lines starting with `#`
+//! are ignored.
+
+use std::path::Path;
+
+use failure::{format_err, ResultExt};
+use serde_yaml::{Mapping, Value};
+
+use super::variable::{parse_assignment, Assignment};
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+/// One assignment from a vars file, and the line it was on.
+pub struct Entry {
+    pub line: usize,
+    pub name: String,
+    pub assignment: Assignment,
+}
+
+fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("yaml") | Some("yml") | Some("json"))
+}
+
+/// The line a top-level key is defined on, or 0 if it can't be found.
+fn line_of_key(contents: &str, key: &str) -> usize {
+    contents
+        .lines()
+        .position(|l| {
+            let l = l.trim_start_matches(['{', ' ']);
+            let l = l.trim_start_matches(['"', '\'']);
+            l.starts_with(key) && l[key.len()..].trim_start_matches(['"', '\'']).starts_with(':')
+        })
+        .map(|i| i + 1)
+        .unwrap_or(0)
+}
+
+fn read_yaml(contents: &str) -> Result<Vec<Entry>> {
+    let map: Mapping = serde_yaml::from_str(contents)?;
+    map.into_iter()
+        .map(|(k, v)| {
+            let name = match k {
+                Value::String(s) => s,
+                _ => return Err(format_err!("variable names must be strings")),
+            };
+            Ok(Entry {
+                line: line_of_key(contents, &name),
+                name,
+                assignment: Assignment::SetValue(v),
+            })
+        })
+        .collect()
+}
+
+fn read_assignments(path: &Path, contents: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, assignment) = parse_assignment(line)
+            .map_err(|e| format_err!("{}:{}: {}", path.display(), i + 1, e))?;
+        entries.push(Entry { line: i + 1, name, assignment });
+    }
+    Ok(entries)
+}
+
+/// Read the assignments in a vars file, in order.
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|_| format!("reading vars file {}", path.display()))?;
+
+    if is_yaml(path) {
+        Ok(read_yaml(&contents).with_context(|_| format!("parsing vars file {}", path.display()))?)
+    } else {
+        read_assignments(path, &contents)
+    }
+}
@@ -0,0 +1,88 @@
+//! Helpers shared by the integration tests. Every `pb` they run gets its own
+//! empty home, config, data and cache directories, so nothing in the
+//! developer's environment can change the result.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+use tempfile::TempDir;
+
+pub type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// A project in a temporary directory.
+pub struct Project {
+    dir: TempDir,
+    home: TempDir,
+}
+
+impl Project {
+    /// An empty directory to run `pb` in.
+    pub fn empty() -> Result<Project> {
+        Ok(Project { dir: tempfile::tempdir()?, home: tempfile::tempdir()? })
+    }
+
+    /// A project with `modules` as its `modules.yaml`, and an empty
+    /// template for build.ninja and for each kind of module and target.
+    pub fn new(modules: &str) -> Result<Project> {
+        let project = Project::empty()?;
+        project.write("modules.yaml", modules)?;
+        for file in &["build.ninja.j2", "exe.default.j2", "lib.default.j2", "target.default.j2"] {
+            project.template(file, "")?;
+        }
+        Ok(project)
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.dir.path().join(path)
+    }
+
+    /// Write a file in the project, creating its directory if needed.
+    pub fn write<P: AsRef<Path>>(&self, path: P, contents: &str) -> Result {
+        let path = self.join(path);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        Ok(std::fs::read_to_string(self.join(path))?)
+    }
+
+    /// Write a file in the project's `templates` directory.
+    pub fn template(&self, name: &str, contents: &str) -> Result {
+        self.write(Path::new("templates").join(name), contents)
+    }
+
+    /// Write the user config file `pb` reads.
+    pub fn user_config(&self, contents: &str) -> Result {
+        let path = self.home.path().join("config").join("bonnibel");
+        std::fs::create_dir_all(&path)?;
+        std::fs::write(path.join("config.yaml"), contents)?;
+        Ok(())
+    }
+
+    /// Where `pb` keeps per-user data, like the list of build directories.
+    pub fn data_home(&self) -> PathBuf {
+        self.home.path().join("data")
+    }
+
+    /// A `pb` command run in the project directory.
+    pub fn pb(&self) -> Command {
+        let home = self.home.path();
+        let mut cmd = Command::main_binary().unwrap();
+        cmd.current_dir(self.path())
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", home)
+            .env("XDG_CONFIG_HOME", home.join("config"))
+            .env("XDG_DATA_HOME", self.data_home())
+            .env("XDG_CACHE_HOME", home.join("cache"));
+        cmd
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+variables:
+  build:
+    type: enum
+    choices: [debug, release]
+    default: debug
+  arch:
+    type: string
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+#[test]
+fn config_set_keeps_other_vars() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2", "{{ vars.arch }} {{ vars.build }}")?;
+
+    proj.pb().args(["init", "arch=x86_64"]).assert().success();
+    proj.pb().args(["config", "set", "build=release"]).assert().success();
+    assert_eq!(proj.read("build/build.ninja")?, "x86_64 release");
+
+    proj.pb().args(["config", "unset", "build"]).assert().success();
+    proj.pb().args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::eq("arch = x86_64 (command line)\nbuild = debug (default)\n"));
+
+    proj.pb().args(["config", "get", "arhc"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown variable 'arhc', did you mean 'arch'?"));
+
+    Ok(())
+}
+
+#[test]
+fn init_imports_environment() -> Result {
+    let proj = Project::new(r#"
+name: test
+templates: templates
+variables:
+  cc:
+    env: CC
+    default: cc
+  cflags:
+    type: list
+    env: CFLAGS
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#)?;
+
+    proj.pb()
+        .env("CC", "clang")
+        .env("CFLAGS", "-O2 -g")
+        .args(["init", "cflags+=-Wall"])
+        .assert()
+        .success();
+
+    proj.pb().args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("cc = clang (env $CC)"))
+        .stdout(predicate::str::contains("cflags = [-O2, -g, -Wall] (command line)"));
+
+    Ok(())
+}
+
+#[test]
+fn user_config_provides_defaults() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2", "{{ vars.arch }} {{ vars.build }}")?;
+    proj.user_config("build_dir: out\nvars:\n  arch: aarch64\n  jobs: 8\n")?;
+
+    proj.pb().args(["init", "build=release"]).assert().success();
+    assert_eq!(proj.read("out/build.ninja")?, "aarch64 release");
+
+    proj.pb().args(["config", "--show-origin"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build_dir = "))
+        .stdout(predicate::str::contains("(user config "))
+        .stdout(predicate::str::contains("build = release (command line)"));
+
+    Ok(())
+}
+
+#[test]
+fn user_config_skips_undeclared_vars() -> Result {
+    let proj = Project::new(r#"
+name: test
+templates: templates
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#)?;
+    proj.template("build.ninja.j2", "{{ vars | length }}")?;
+    proj.user_config("vars:\n  jobs: 8\n")?;
+
+    proj.pb().arg("init").assert().success();
+    assert_eq!(proj.read("build/build.ninja")?, "0");
+
+    proj.pb().args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("jobs").not());
+
+    Ok(())
+}
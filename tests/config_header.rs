@@ -0,0 +1,66 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+variables:
+  build:
+    type: enum
+    choices: [debug, release]
+    default: debug
+  smp:
+    type: bool
+    default: true
+  trace:
+    type: bool
+    default: false
+  cpus:
+    type: int
+    default: 4
+  use-apic:
+    type: bool
+    default: true
+config_header:
+  path: include/config.h
+  rust_cfg: cfg.args
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+#[test]
+fn config_header_written_when_changed() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("exe.default.j2", "{{ config_header }}")?;
+
+    proj.pb().arg("init").assert().success();
+
+    let header = proj.join("build/include/config.h");
+    let contents = std::fs::read_to_string(&header)?;
+    assert!(contents.contains("#define CONFIG_BUILD \"debug\"\n#define CONFIG_BUILD_DEBUG 1\n"));
+    assert!(contents.contains("#define CONFIG_CPUS 4\n"));
+    assert!(contents.contains("#define CONFIG_SMP 1\n"));
+    assert!(contents.contains("/* CONFIG_TRACE is not set */\n"));
+    assert!(contents.contains("#define CONFIG_USE_APIC 1\n"));
+
+    assert_eq!(proj.read("build/cfg.args")?, "--cfg build=\"debug\"\n--cfg cpus=\"4\"\n--cfg smp\n--cfg use_apic\n");
+    assert_eq!(proj.read("build/kernel.ninja")?, header.canonicalize()?.to_str().unwrap());
+
+    // Regenerating with the same vars leaves the header alone
+    let modified = std::fs::metadata(&header)?.modified()?;
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    proj.pb().arg("generate").assert().success();
+    assert_eq!(std::fs::metadata(&header)?.modified()?, modified);
+
+    proj.pb().args(["config", "set", "trace=yes"]).assert().success();
+    assert!(std::fs::read_to_string(&header)?.contains("#define CONFIG_TRACE 1\n"));
+
+    Ok(())
+}
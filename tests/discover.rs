@@ -0,0 +1,58 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: [src/kernel/main.c]
+"#;
+
+#[test]
+fn discover_from_subdirectory() -> Result {
+    let proj = Project::new(MODULES)?;
+    let subdir = proj.join("src/kernel");
+    std::fs::create_dir_all(&subdir)?;
+
+    proj.pb().current_dir(&subdir).arg("templates")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("module kernel: exe.default.j2"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn run_from_build_directory() -> Result {
+    use std::os::unix::fs::PermissionsExt;
+
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2", "# {{ buildroot }}")?;
+    proj.pb().arg("init").assert().success();
+
+    let build = proj.join("build");
+    std::fs::remove_file(build.join("build.ninja"))?;
+    proj.pb().current_dir(&build).arg("generate").assert().success();
+    assert!(proj.read("build/build.ninja")?.starts_with("# "));
+
+    // A stand-in for ninja that records how it was run
+    proj.write("bin/ninja", "#!/bin/sh\necho \"$@\" > \"$(dirname \"$0\")/args\"\n")?;
+    std::fs::set_permissions(proj.join("bin/ninja"), std::fs::Permissions::from_mode(0o755))?;
+    let path = std::env::join_paths(
+        std::iter::once(proj.join("bin")).chain(std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default())))?;
+
+    proj.pb().current_dir(&build).env("PATH", path).arg("build").assert().success();
+    let args = proj.read("bin/args")?;
+    let dir = args.trim().strip_prefix("-C ").expect("ninja run without -C");
+    assert_eq!(std::fs::canonicalize(build.join(dir))?, build.canonicalize()?);
+
+    Ok(())
+}
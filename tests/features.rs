@@ -0,0 +1,70 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+modules:
+  debugger:
+    kind: lib
+    output: debugger.a
+    enabled_if: debug_tools
+    source: [debugger.c]
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    deps: [debugger]
+    source: [kernel.c]
+"#;
+
+#[test]
+fn depends_on_disabled_module() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().args(["init", "debug_tools=off"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "module 'kernel' depends on 'debugger', which is disabled: it requires `debug_tools`"));
+
+    Ok(())
+}
+
+#[test]
+fn disabled_modules_are_left_out() -> Result {
+    let proj = Project::new(&MODULES.replace("deps: [debugger]", "optional_depends: [debugger]"))?;
+    proj.template("exe.default.j2", "{{ module.active_depends | join(sep=\",\") }}")?;
+
+    proj.pb().args(["init", "debug_tools=off"]).assert().success();
+    assert_eq!(proj.read("build/kernel.ninja")?, "");
+    assert!(!proj.join("build/debugger.ninja").exists());
+
+    proj.pb().args(["config", "set", "debug_tools=on"]).assert().success();
+    assert_eq!(proj.read("build/kernel.ninja")?, "debugger");
+    assert!(proj.join("build/debugger.ninja").exists());
+
+    Ok(())
+}
+
+#[test]
+fn unknown_variable_in_enabled_if() -> Result {
+    let declared = MODULES.replace("modules:\n",
+        "variables:\n  debug_tools:\n    type: bool\n    default: false\nmodules:\n");
+    let proj = Project::new(&declared.replace("enabled_if: debug_tools", "enabled_if: debug_tols"))?;
+
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Unknown variable 'debug_tols', did you mean 'debug_tools'?"));
+
+    // Values compared against aren't variables
+    proj.write("modules.yaml", &declared.replace("enabled_if: debug_tools", "enabled_if: debug_tools == true"))?;
+    proj.pb().args(["init", "debug_tools=true"]).assert().success();
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"{
+    "name": "test",
+    "templates": "templates",
+    "modules": {
+        "kernel": {
+            "kind": "exe",
+            "target": "kernel",
+            "output": "kernel.elf",
+            "source": ["kernel.c"]
+        }
+    }
+}"#;
+
+#[test]
+fn json_project_file() -> Result {
+    let proj = Project::empty()?;
+    proj.write("modules.json", MODULES)?;
+    proj.template("exe.default.j2", "")?;
+    proj.template("target.default.j2", "")?;
+
+    proj.pb().arg("templates")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("module kernel: exe.default.j2"));
+
+    Ok(())
+}
+
+const TOML_MODULES: &str = r#"
+name = "test"
+templates = "templates"
+
+[vars]
+arch = "x86_64"
+
+[modules.kernel]
+kind = "exe"
+target = "kernel"
+output = "kernel.elf"
+depends = ["kutil"]
+source = ["kernel.c"]
+
+[modules.kutil]
+kind = "lib"
+output = "kutil.a"
+source = ["kutil.c"]
+"#;
+
+#[test]
+fn toml_project_file() -> Result {
+    let proj = Project::empty()?;
+    proj.write("modules.toml", TOML_MODULES)?;
+    proj.template("build.ninja.j2", "")?;
+    proj.template("exe.default.j2", "{{ module.name }} {{ module.depends | join(sep=\",\") }} {{ vars.arch }}")?;
+    proj.template("lib.default.j2", "")?;
+    proj.template("target.default.j2", "")?;
+
+    proj.pb().arg("init").assert().success();
+    assert_eq!(proj.read("build/kernel.ninja")?, "kernel kutil x86_64");
+
+    Ok(())
+}
+
+#[test]
+fn unknown_extension_is_yaml() -> Result {
+    let proj = Project::empty()?;
+    proj.write("project.conf", "name: test\ntemplates: templates\nmodules:\n  kernel:\n    kind: exe\n    target: kernel\n    output: kernel.elf\n    source: [kernel.c]\n")?;
+    proj.template("exe.default.j2", "")?;
+    proj.template("target.default.j2", "")?;
+
+    proj.pb().args(["-f", "project.conf", "templates"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("module kernel: exe.default.j2"));
+
+    Ok(())
+}
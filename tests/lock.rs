@@ -0,0 +1,64 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use fs2::FileExt;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Stdio;
+use std::time::Duration;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+#[test]
+fn generate_waits_for_lock() -> Result {
+    let proj = Project::new(MODULES)?;
+    for file in &["build.ninja.j2", "exe.default.j2", "target.default.j2"] {
+        proj.template(file, "# {{ buildroot }}")?;
+    }
+
+    proj.pb().arg("init").assert().success();
+
+    let build = proj.join("build");
+    let lock = std::fs::File::open(build.join(".bonnibel_lock"))?;
+    lock.lock_exclusive()?;
+
+    let mut child = proj.pb()
+        .arg("generate")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(child.try_wait()?.is_none(), "generate didn't wait for the lock");
+
+    lock.unlock()?;
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Waiting for another pb"));
+
+    // Generated files are renamed into place, leaving nothing behind
+    let mut names: Vec<String> = std::fs::read_dir(&build)?
+        .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+        .collect::<std::result::Result<_, _>>()?;
+    names.sort();
+    assert_eq!(names, [".bonnibel_lock", ".bonnibel_vars", "build.ninja", "kernel", "kernel.ninja"]);
+
+    // New files get the same permissions as any other file created under
+    // this umask, not the temporary file's owner-only ones
+    let reference = proj.join("reference");
+    std::fs::File::create(&reference)?;
+    let expected = std::fs::metadata(&reference)?.permissions().mode();
+    let mode = std::fs::metadata(build.join("build.ninja"))?.permissions().mode();
+    assert_eq!(mode & 0o777, expected & 0o777);
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+variables:
+  arch:
+    type: enum
+    choices: [x86_64, aarch64]
+    default: x86_64
+  build:
+    type: enum
+    choices: [debug, release]
+    default: debug
+  cc:
+    default: cc
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+#[test]
+fn matrix_init_and_generate_all() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2", "{{ vars.arch }} {{ vars.build }} {{ vars.cc }}")?;
+
+    proj.pb().args(["init", "--matrix", "arch=x86_64,aarch64", "--matrix", "build=debug,release", "cc=clang"])
+        .assert()
+        .success();
+
+    for arch in &["x86_64", "aarch64"] {
+        for build in &["debug", "release"] {
+            let ninja = proj.read(format!("build-{}-{}/build.ninja", arch, build))?;
+            assert_eq!(ninja, format!("{} {} clang", arch, build));
+        }
+    }
+    assert!(proj.data_home().join("bonnibel/build_dirs.yaml").is_file());
+
+    proj.pb().args(["init", "--matrix", "arch=aarch64", "--matrix", "build=debug,release", "--matrix-dir", "out/{build}"])
+        .assert()
+        .success();
+    assert!(proj.join("out/release/build.ninja").is_file());
+
+    // Every build directory is regenerated, each noticing the change
+    proj.write("modules.yaml", &format!("{}\n# changed\n", MODULES))?;
+    proj.pb().args(["generate", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("modules.yaml has changed").count(6));
+
+    proj.pb().args(["init", "--matrix", "arch=x86_64", "--matrix", "build=debug,release", "--matrix-dir", "out/{arch}"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("more than one combination"));
+
+    Ok(())
+}
+
+#[test]
+fn matrix_takes_several_axes() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2", "{{ vars.arch }} {{ vars.build }} {{ vars.cc }}")?;
+
+    proj.pb().args(["init", "--matrix", "arch=x86_64,aarch64", "build=debug,release"])
+        .assert()
+        .success();
+    for arch in &["x86_64", "aarch64"] {
+        for build in &["debug", "release"] {
+            let ninja = proj.read(format!("build-{}-{}/build.ninja", arch, build))?;
+            assert_eq!(ninja, format!("{} {} cc", arch, build));
+        }
+    }
+
+    // An axis with one value is set everywhere but leaves the names alone
+    std::fs::create_dir(proj.join("out"))?;
+    proj.pb().args(["-d", "out", "init", "--matrix", "arch=x86_64,aarch64", "cc=clang"])
+        .assert()
+        .success();
+    assert_eq!(proj.read("out-aarch64/build.ninja")?, "aarch64 debug clang");
+
+    proj.pb().args(["init", "--matrix", "arch=x86_64,aarch64", "cc+=clang"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("give other variables before --matrix"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_builds_fail() -> Result {
+    use std::os::unix::fs::PermissionsExt;
+
+    let proj = Project::new(MODULES)?;
+    proj.pb().args(["init", "--matrix", "arch=x86_64,aarch64"]).assert().success();
+
+    // A stand-in for ninja that always fails
+    proj.write("bin/ninja", "#!/bin/sh\nexit 1\n")?;
+    std::fs::set_permissions(proj.join("bin/ninja"), std::fs::Permissions::from_mode(0o755))?;
+    let path = std::env::join_paths(
+        std::iter::once(proj.join("bin")).chain(std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default())))?;
+
+    proj.pb().args(["-d", "build-x86_64", "build"]).env("PATH", &path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build failed in"));
+    proj.pb().args(["build", "--all"]).env("PATH", &path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build failed in"));
+
+    Ok(())
+}
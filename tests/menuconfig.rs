@@ -0,0 +1,37 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+#[test]
+fn menuconfig_needs_a_terminal() -> Result {
+    let proj = Project::new(r#"
+name: test
+templates: templates
+variables:
+  smp:
+    type: bool
+    default: true
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#)?;
+
+    proj.pb().arg("menuconfig")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pb init"));
+
+    proj.pb().arg("init").assert().success();
+
+    proj.pb().arg("menuconfig")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("needs an interactive terminal"));
+
+    Ok(())
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    deps: [kutil]
+    source: [kernel.c]
+  kutil:
+    kind: lib
+    output: kutil.a
+    source: [kutil.c]
+"#;
+
+#[test]
+fn migrate_unversioned() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().arg("migrate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("renamed `deps` to `depends` in module 'kernel'"))
+        .stdout(predicate::str::contains("set schema version 1 -> 2"));
+
+    let migrated = proj.read("modules.yaml")?;
+    assert!(migrated.contains("schema: 2"));
+    assert!(!migrated.contains("deps:"));
+
+    Ok(())
+}
+
+#[test]
+fn newer_schema_rejected() -> Result {
+    let proj = Project::new(&format!("bonnibel: {{ schema: 99 }}\n{}", MODULES))?;
+
+    proj.pb().arg("templates")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("uses schema 99"));
+
+    Ok(())
+}
+
+#[test]
+fn migrate_keeps_comments_and_merges_depends() -> Result {
+    let proj = Project::new("\
+# The test project
+name: test
+templates: templates
+
+modules:
+  # The kernel itself
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    deps:
+      - kutil # utilities
+      - elf
+    depends: [elf]
+    source: [kernel.c]
+
+  elf:
+    kind: lib
+    output: elf.a
+    deps: [kutil]
+    source: [elf.c]
+
+  kutil:
+    kind: lib
+    output: kutil.a
+    source: [kutil.c]
+")?;
+
+    proj.pb().arg("migrate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("merged `deps` into `depends` in module 'kernel'"))
+        .stdout(predicate::str::contains("renamed `deps` to `depends` in module 'elf'"));
+
+    let version = env!("CARGO_PKG_VERSION").split('.').take(2).collect::<Vec<_>>().join(".");
+    assert_eq!(proj.read("modules.yaml")?, format!("\
+# The test project
+bonnibel:
+  schema: 2
+  requires: \">={}\"
+
+name: test
+templates: templates
+
+modules:
+  # The kernel itself
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    depends: [elf, kutil]
+    source: [kernel.c]
+
+  elf:
+    kind: lib
+    output: elf.a
+    depends: [kutil]
+    source: [elf.c]
+
+  kutil:
+    kind: lib
+    output: kutil.a
+    source: [kutil.c]
+", version));
+
+    Ok(())
+}
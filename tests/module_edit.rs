@@ -0,0 +1,126 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = "\
+# The test project
+name: test
+templates: templates
+
+modules:
+  # The kernel itself
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    depends:
+      - kutil # utilities
+    source: [kernel.c]
+
+  kutil:
+    kind: lib
+    output: kutil.a
+    source: [kutil.c]
+";
+
+const EXPECTED: &str = "\
+# The test project
+name: test
+templates: templates
+
+modules:
+  # The kernel itself
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    depends:
+      - kutil # utilities
+      - elf
+    source: [kernel.c]
+
+  kutil:
+    kind: lib
+    output: kutil.a
+    source: [kutil.c]
+
+  elf:
+    kind: lib
+    output: elf.a
+    source: [elf.c]
+";
+
+#[test]
+fn add_module_and_dependency() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().args(["module", "add", "elf", "--source", "elf.c"]).assert().success();
+    proj.pb().args(["module", "add-dep", "kernel", "elf"]).assert().success();
+
+    assert_eq!(proj.read("modules.yaml")?, EXPECTED);
+    Ok(())
+}
+
+#[test]
+fn remove_depended_on_module() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().args(["module", "rm", "kutil"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("module 'kernel' depends on unknown module 'kutil'"));
+
+    assert_eq!(proj.read("modules.yaml")?, MODULES);
+    Ok(())
+}
+
+#[test]
+fn remove_module_and_its_comments() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().args(["module", "rm", "kernel"]).assert().success();
+
+    assert_eq!(proj.read("modules.yaml")?, "\
+# The test project
+name: test
+templates: templates
+
+modules:
+  kutil:
+    kind: lib
+    output: kutil.a
+    source: [kutil.c]
+");
+    Ok(())
+}
+
+#[test]
+fn edit_project_with_gated_module() -> Result {
+    let proj = Project::new(&format!("{}    enabled_if: has_kutil\n", MODULES))?;
+
+    proj.pb().args(["module", "add", "elf", "--source", "elf.c"]).assert().success();
+    assert!(proj.read("modules.yaml")?.contains("  elf:\n"));
+
+    Ok(())
+}
+
+#[test]
+fn add_existing_or_self_dependency() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().args(["module", "add-dep", "kernel", "kutil"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already depends on kutil"));
+    assert_eq!(proj.read("modules.yaml")?, MODULES);
+
+    proj.pb().args(["module", "add-dep", "kutil", "kutil"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("module 'kutil' can't depend on itself"));
+    assert_eq!(proj.read("modules.yaml")?, MODULES);
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+fn new_project_initializes(template: &str, files: &[&str]) -> Result {
+    let proj = Project::empty()?;
+
+    proj.pb().args(["new", "--template", template]).assert().success();
+    proj.pb().arg("init").assert().success();
+
+    assert!(proj.join("build/build.ninja").exists());
+    for file in files {
+        assert!(proj.join("build").join(file).exists(), "{} has no {}", template, file);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn new_c_project() -> Result {
+    new_project_initializes("c", &["hello.ninja", "greeting.ninja", "native/target.ninja"])
+}
+
+#[test]
+fn new_cpp_project() -> Result {
+    new_project_initializes("cpp", &["hello.ninja", "greeting.ninja", "native/target.ninja"])
+}
+
+#[test]
+fn new_asm_kernel_project() -> Result {
+    new_project_initializes("asm-kernel", &["kernel.ninja", "kernel/target.ninja"])
+}
+
+#[test]
+fn new_refuses_existing_project() -> Result {
+    let proj = Project::empty()?;
+    proj.write("modules.yaml", "name: test\n")?;
+
+    proj.pb().arg("new")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("modules.yaml"));
+
+    Ok(())
+}
+
+#[test]
+fn new_writes_nothing_over_existing_files() -> Result {
+    let proj = Project::empty()?;
+    proj.write("src/hello/main.c", "int main() { return 0; }\n")?;
+
+    proj.pb().args(["new", "--template", "c"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("main.c\" already exists"));
+
+    assert!(!proj.join("modules.yaml").exists());
+    assert!(!proj.join("templates").exists());
+
+    Ok(())
+}
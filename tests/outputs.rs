@@ -0,0 +1,49 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    outputs:
+      map: kernel.map
+    source: []
+  loader:
+    kind: exe
+    target: boot
+    output: loader.elf
+    depends: [kernel:map]
+    source: []
+"#;
+
+#[test]
+fn named_outputs() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2", r#"{{ output(ref="kernel") }} {{ output(ref="kernel:map") }}"#)?;
+    proj.template("exe.default.j2", r#"{{ depoutputs["kernel:map"] | default(value="") }}"#)?;
+
+    proj.pb().arg("init").assert().success();
+    assert_eq!(proj.read("build/build.ninja")?, "kernel.elf kernel.map");
+    assert_eq!(proj.read("build/loader.ninja")?, "kernel.map");
+
+    proj.template("build.ninja.j2", r#"{{ output(ref="kernel:symbols") }}"#)?;
+    proj.pb().arg("generate")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no module output 'kernel:symbols'"));
+
+    proj.write("modules.yaml", &MODULES.replace("[kernel:map]", "[kernel:symbols]"))?;
+    proj.pb().arg("generate")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("module 'kernel' has no output named 'symbols'"));
+
+    Ok(())
+}
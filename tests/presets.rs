@@ -0,0 +1,66 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+variables:
+  build:
+    type: enum
+    choices: [debug, release]
+    default: debug
+  jobs:
+    type: int
+    default: 1
+  lto:
+    type: bool
+    default: false
+presets:
+  base:
+    vars:
+      jobs: 8
+  release:
+    inherits: base
+    vars:
+      build: release
+      lto: true
+  loop:
+    inherits: loop
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+#[test]
+fn preset_inherits_and_cli_overrides() -> Result {
+    let proj = Project::new(&MODULES.replace("  loop:\n    inherits: loop\n", ""))?;
+    proj.template("build.ninja.j2", "{{ preset }} {{ vars.build }} {{ vars.jobs }} {{ vars.lto }}")?;
+
+    proj.pb().args(["init", "--preset", "release", "lto=no"]).assert().success();
+    assert_eq!(proj.read("build/build.ninja")?, "release release 8 false");
+
+    proj.pb().args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("jobs = 8 (preset base)"));
+
+    Ok(())
+}
+
+#[test]
+fn preset_cycle_is_an_error() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("preset 'loop' inherits from itself"));
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+probes:
+  has_true:
+    command: ["true"]
+  has_missing:
+    command: [bonnibel-no-such-program]
+  greeting:
+    command: [echo, "version ${release}"]
+    result: output
+    regex: 'version (\d+)'
+vars:
+  release: "42"
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+    optional_depends: [missing]
+  missing:
+    kind: lib
+    output: missing.a
+    source: []
+    enabled_if: has_missing
+"#;
+
+#[test]
+fn probes_run_and_cache() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2", "{{ vars.has_true }} {{ vars.has_missing }} {{ vars.greeting }}")?;
+
+    proj.pb().arg("init")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Probe has_true: yes\n"))
+        .stdout(predicate::str::contains("Probe has_missing: no\n"));
+
+    assert_eq!(proj.read("build/build.ninja")?, "true false 42");
+    assert!(!proj.join("build/missing.ninja").exists());
+
+    proj.pb().arg("init")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Probe greeting: 42 (cached)"));
+
+    // Changing a variable in the command line runs the probe again
+    proj.pb().args(["init", "release=7"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Probe greeting: 7\n"))
+        .stdout(predicate::str::contains("Probe has_true: yes (cached)"));
+
+    // And so does changing it in the build directory
+    proj.pb().args(["config", "set", "release=9"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Probe greeting: 9\n"));
+    assert_eq!(proj.read("build/build.ninja")?, "true false 9");
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{Project, Result};
+use jsonschema::JSONSchema;
+
+const SCHEMA: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/schema/modules.schema.json"));
+
+/// A project file using every section.
+const MODULES: &str = r#"
+bonnibel:
+  schema: 2
+  requires: ">=2.3"
+name: test
+templates: templates
+variables:
+  build:
+    type: enum
+    choices: [debug, release]
+    default: debug
+    help: Which kind of build to make
+  cc:
+    env: CC
+    default: cc
+  cflags:
+    type: list
+    default: [-O2]
+vars:
+  jobs: 4
+presets:
+  release:
+    help: An optimized build
+    vars:
+      build: release
+probes:
+  has_nasm:
+    command: [nasm, -v]
+  ld_version:
+    command: ["${ld}", --version]
+    result: output
+    regex: '(\d+\.\d+)'
+config_header:
+  path: config.h
+  rust_cfg: cfg.args
+toolchains:
+  cross:
+    cc:
+      programs: [x86_64-elf-gcc, gcc]
+      version: ">=10"
+    as: nasm
+    flags:
+      cflags: [-ffreestanding]
+    sysroot: sysroot
+targets:
+  kernel:
+    template: kernel-target
+    toolchain: cross
+overlays:
+  - url: https://example.com/acpica.tar.gz
+    path: external/acpica
+subprojects:
+  - name: libc
+    path: external/libc
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    outputs:
+      map: kernel.map
+    depends: [kutil]
+    optional_depends: [debugger]
+    extras: [kernel.ld]
+    source: [kernel.c]
+  kutil:
+    kind: lib
+    output: kutil.a
+    visibility: [kernel]
+    source: [kutil.c]
+  debugger:
+    kind: lib
+    output: debugger.a
+    enabled_if: build == debug && has_nasm
+    visibility: private
+    source: [debugger.c]
+"#;
+
+fn validate(modules: &str) -> std::result::Result<(), Vec<String>> {
+    let schema: serde_json::Value = serde_json::from_str(SCHEMA).unwrap();
+    let compiled = JSONSchema::compile(&schema).expect("the schema doesn't compile");
+
+    let instance: serde_json::Value = serde_yaml::from_str(modules).unwrap();
+    let result = compiled.validate(&instance);
+    result.map_err(|errors| errors.map(|e| format!("{} at {}", e, e.instance_path)).collect())
+}
+
+/// The published schema must match the one generated from the project
+/// types. If this fails, regenerate it with:
+///
+///   cargo run --bin pb -- schema > schema/modules.schema.json
+#[test]
+fn published_schema_is_current() -> Result {
+    let output = Project::empty()?.pb().arg("schema").output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, SCHEMA,
+        "schema/modules.schema.json is out of date");
+
+    Ok(())
+}
+
+#[test]
+fn schema_accepts_project_file() {
+    if let Err(errors) = validate(MODULES) {
+        panic!("schema rejects the sample project: {}", errors.join(", "));
+    }
+
+    assert!(validate(&MODULES.replace("kind: lib", "kind: library")).is_err());
+    assert!(validate(&MODULES.replace("templates: templates", "")).is_err());
+}
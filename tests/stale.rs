@@ -0,0 +1,59 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+#[test]
+fn generate_reports_changes() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().arg("init").assert().success();
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Regenerating").not());
+
+    proj.write("modules.yaml", &format!("{}\n# changed\n", MODULES))?;
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("modules.yaml has changed"));
+
+    // A state file from an older Bonnibel records no version
+    let contents = proj.read("build/.bonnibel_vars")?;
+    let old: Vec<&str> = contents.lines().filter(|l| !l.starts_with("bonnibel:")).collect();
+    proj.write("build/.bonnibel_vars", &old.join("\n"))?;
+
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("older version of Bonnibel"))
+        .stdout(predicate::str::contains("has changed").not());
+
+    // The state is stamped again once regenerated
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Regenerating").not());
+
+    // Another project file can't be used with this build directory
+    proj.write("other.yaml", MODULES)?;
+    proj.pb().args(["-f", "other.yaml", "-d", "build", "generate"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("was initialized from"));
+
+    Ok(())
+}
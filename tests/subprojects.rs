@@ -0,0 +1,161 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+subprojects:
+  - name: util
+    path: libs
+vars:
+  width: 4
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    depends: [util.fmt]
+    source: []
+"#;
+
+const SUB_MODULES: &str = r#"
+name: util
+templates: templates
+vars:
+  width: 8
+  fill: "-"
+modules:
+  fmt:
+    kind: lib
+    output: fmt.a
+    depends: [base]
+    source: []
+  base:
+    kind: lib
+    output: base.a
+    source: []
+"#;
+
+#[test]
+fn subproject_modules_are_namespaced() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("exe.default.j2", "main {{ module.depends | join(sep=\",\") }} {{ vars.width }}{{ vars.fill }}")?;
+    proj.write("libs/modules.yaml", SUB_MODULES)?;
+    proj.write("libs/templates/lib.default.j2", "sub {{ module.root }} {{ module.depends | join(sep=\",\") }}")?;
+
+    proj.pb().arg("init").assert().success();
+
+    let root = proj.path().canonicalize()?;
+    assert_eq!(proj.read("build/kernel.ninja")?, "main util.fmt 4-");
+    assert_eq!(proj.read("build/util.fmt.ninja")?,
+        format!("sub {} util.base", root.join("libs").display()));
+    assert_eq!(proj.read("build/util.base.ninja")?,
+        format!("sub {} ", root.join("libs").display()));
+
+    Ok(())
+}
+
+#[test]
+fn subproject_cycle_is_an_error() -> Result {
+    let proj = Project::new(r#"
+name: test
+templates: templates
+subprojects:
+  - name: me
+    path: .
+modules: {}
+"#)?;
+
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("subproject cycle: "));
+
+    Ok(())
+}
+
+#[test]
+fn subproject_target_options_rejected() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.write("libs/modules.yaml", &format!(r#"{}
+  test:
+    kind: exe
+    target: host
+    output: test
+    source: []
+targets:
+  host:
+    template: other
+"#, SUB_MODULES))?;
+    proj.write("libs/templates/lib.default.j2", "")?;
+
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("sets options for target 'host'"));
+
+    Ok(())
+}
+
+#[test]
+fn subproject_variables_gate_its_modules() -> Result {
+    let proj = Project::new(r#"
+name: test
+templates: templates
+subprojects:
+  - name: util
+    path: libs
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    depends: [util.dbg]
+    source: []
+"#)?;
+    proj.write("libs/modules.yaml", r#"
+name: util
+templates: templates
+variables:
+  debug:
+    type: bool
+    default: true
+probes:
+  has_sh:
+    command: [sh, -c, "true"]
+modules:
+  dbg:
+    kind: lib
+    output: dbg.a
+    enabled_if: debug && has_sh
+    source: []
+"#)?;
+    proj.write("libs/templates/lib.default.j2", "{{ vars.debug }}")?;
+
+    proj.pb().arg("init").assert().success();
+    assert_eq!(proj.read("build/util.dbg.ninja")?, "true");
+
+    proj.pb().args(["init", "debug=false"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("depends on 'util.dbg', which is disabled"));
+
+    Ok(())
+}
+
+#[test]
+fn subproject_presets_rejected() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.write("libs/modules.yaml", &format!("{}presets:\n  small:\n    vars:\n      width: 2\n", SUB_MODULES))?;
+    proj.write("libs/templates/lib.default.j2", "")?;
+
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("it has preset 'small', but only the top-level project may have presets"));
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+targets:
+  host:
+    template: host-target
+modules:
+  kmain:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    depends: [klib, util]
+    source: [kernel.c]
+  loader:
+    kind: exe
+    target: uefi
+    output: loader.efi
+    depends: [util]
+    source: [loader.c]
+  shell:
+    kind: exe
+    target: uefi
+    output: shell.efi
+    source: [shell.c]
+  tool:
+    kind: exe
+    target: host
+    output: tool
+    template: shared
+    source: [tool.c]
+  klib:
+    kind: lib
+    output: klib.a
+    source: [klib.c]
+  util:
+    kind: lib
+    output: util.a
+    source: [util.c]
+"#;
+
+#[test]
+fn template_lookup_chain() -> Result {
+    let proj = Project::new(MODULES)?;
+    let templates = [
+        "exe.kmain.kernel.j2", "exe.kernel.j2", "exe.loader.j2", "exe.uefi.j2",
+        "lib.kernel.j2", "shared.j2", "host-target.j2",
+    ];
+    for file in &templates {
+        proj.template(file, "")?;
+    }
+
+    proj.pb().arg("templates")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "module kmain: exe.kmain.kernel.j2 (matched name 'kmain' and target 'kernel')"))
+        .stdout(predicate::str::contains("module loader: exe.loader.j2 (matched name 'loader')"))
+        .stdout(predicate::str::contains("module shell: exe.uefi.j2 (matched target 'uefi')"))
+        .stdout(predicate::str::contains("module tool: shared.j2 (explicit `template` key)"))
+        .stdout(predicate::str::contains("module klib: lib.kernel.j2 (matched target 'kernel')"))
+        // Used by two targets, so only rendered once
+        .stdout(predicate::str::contains("module util: lib.default.j2 (default for kind 'lib')"))
+        .stdout(predicate::str::contains("target host: host-target.j2 (explicit `template` key)"))
+        .stdout(predicate::str::contains("target kernel: target.default.j2 (default for kind 'target')"));
+
+    Ok(())
+}
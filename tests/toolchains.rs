@@ -0,0 +1,81 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+use std::os::unix::fs::PermissionsExt;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+variables:
+  bin:
+    type: path
+toolchains:
+  cross:
+    cc:
+      programs: ["${bin}/oldcc", "${bin}/newcc"]
+      version: ">=10"
+    ar: "${bin}/oldcc"
+    flags:
+      cflags: [-ffreestanding]
+targets:
+  kernel:
+    toolchain: cross
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+fn fake_tool(path: &std::path::Path, version: &str) -> std::io::Result<()> {
+    std::fs::write(path, format!("#!/bin/sh\necho \"fake cc {}\"\n", version))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+}
+
+#[test]
+fn toolchain_detected() -> Result {
+    let proj = Project::new(MODULES)?;
+    proj.template("exe.default.j2", "{{ toolchain.name }}")?;
+    proj.template("target.default.j2",
+        "{{ toolchain.cc }} {{ toolchain.versions.cc }} {{ toolchain.flags.cflags.0 }}")?;
+
+    let bin = proj.join("bin");
+    std::fs::create_dir(&bin)?;
+    fake_tool(&bin.join("oldcc"), "9.3.0")?;
+    fake_tool(&bin.join("newcc"), "12.1")?;
+
+    proj.pb().arg("init").arg(format!("bin={}", bin.display()))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Toolchain cross cc:"));
+
+    let target = proj.read("build/kernel/target.ninja")?;
+    assert_eq!(target, format!("{} 12.1.0 -ffreestanding", bin.join("newcc").display()));
+    assert_eq!(proj.read("build/kernel.ninja")?, "cross");
+
+    // Regenerating detects the toolchain again, saying only what changed
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Toolchain").not());
+
+    fake_tool(&bin.join("newcc"), "13.2")?;
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Toolchain cross cc:"));
+    let target = proj.read("build/kernel/target.ninja")?;
+    assert_eq!(target, format!("{} 13.2.0 -ffreestanding", bin.join("newcc").display()));
+
+    // No program of the right version is an error
+    std::fs::remove_file(bin.join("newcc"))?;
+    proj.pb().arg("init").arg(format!("bin={}", bin.display()))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("oldcc is version 9.3.0"));
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+variables:
+  build:
+    type: enum
+    choices: [debug, release]
+    default: debug
+    help: Which kind of build to make
+  jobs:
+    type: int
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+#[test]
+fn unknown_variable_suggests_name() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().args(["init", "buidl=release"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("did you mean 'build'?"));
+
+    proj.pb().args(["init", "build=fast"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected one of: debug, release"));
+
+    Ok(())
+}
+
+#[test]
+fn vars_lists_defaults_and_help() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().args(["init", "jobs=4"]).assert().success();
+
+    proj.pb().arg("vars")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build (enum) = debug [debug, release]"))
+        .stdout(predicate::str::contains("    Which kind of build to make"))
+        .stdout(predicate::str::contains("jobs (int) = 4"));
+
+    Ok(())
+}
+
+#[test]
+fn list_variables_append_and_remove() -> Result {
+    let proj = Project::new(r#"
+name: test
+templates: templates
+variables:
+  cflags:
+    type: list
+    default: [-O2, -Wall]
+  debug:
+    type: bool
+    default: no
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#)?;
+    proj.template("build.ninja.j2",
+        "{% for f in vars.cflags %}{{ f }} {% endfor %}{% if not vars.debug %}nodebug{% endif %}")?;
+
+    proj.pb().args(["init", "cflags+=-g", "cflags-=-Wall"]).assert().success();
+    assert_eq!(proj.read("build/build.ninja")?, "-O2 -g nodebug");
+
+    proj.pb().args(["init", "cflags=-g"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is a list"));
+
+    Ok(())
+}
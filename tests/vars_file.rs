@@ -0,0 +1,95 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+variables:
+  build:
+    type: enum
+    choices: [debug, release]
+    default: debug
+  jobs:
+    type: int
+    default: 1
+  cflags:
+    type: list
+    default: []
+modules:
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    source: []
+"#;
+
+fn project() -> Result<Project> {
+    let proj = Project::new(MODULES)?;
+    proj.template("build.ninja.j2",
+        "{{ vars.build }} {{ vars.jobs }} {{ vars.cflags | join(sep=\",\") }}")?;
+    Ok(proj)
+}
+
+#[test]
+fn vars_files_apply_in_order() -> Result {
+    let proj = project()?;
+    proj.write("ci.yaml", "build: release\njobs: 4\ncflags: [-O2]\n")?;
+    proj.write("local.env", "# local overrides\njobs=6\ncflags+=-g\n")?;
+
+    proj.pb().args(["init", "--vars-file", "ci.yaml", "--vars-file", "local.env", "jobs=8"])
+        .assert()
+        .success();
+    assert_eq!(proj.read("build/build.ninja")?, "release 8 -O2,-g");
+
+    Ok(())
+}
+
+#[test]
+fn vars_file_errors_have_lines() -> Result {
+    let proj = project()?;
+    proj.write("ci.yaml", "build: release\n\njobs: many\n")?;
+    proj.write("local.env", "jobs=2\nbuild\n")?;
+
+    proj.pb().args(["init", "-F", "ci.yaml"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("ci.yaml:3: variable 'jobs' is an int"));
+
+    proj.pb().args(["init", "-F", "local.env"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("local.env:2: Variable build does not parse"));
+
+    Ok(())
+}
+
+#[test]
+fn vars_file_changes_are_reported() -> Result {
+    let proj = project()?;
+    proj.write("ci.yaml", "build: release\n")?;
+
+    proj.pb().args(["init", "-F", "ci.yaml"]).assert().success();
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Regenerating").not());
+
+    proj.write("ci.yaml", "build: debug\n")?;
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ci.yaml has changed, re-run `pb init` to apply it"));
+
+    // Init reads the file again and records it
+    proj.pb().args(["init", "-F", "ci.yaml"]).assert().success();
+    assert_eq!(proj.read("build/build.ninja")?, "debug 1 ");
+    proj.pb().arg("generate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Regenerating").not());
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{Project, Result};
+use predicates::prelude::*;
+
+const MODULES: &str = r#"
+name: test
+templates: templates
+modules:
+  kutil:
+    kind: lib
+    output: kutil.a
+    visibility: [kernel]
+    source: [kutil.c]
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    deps: [kutil]
+    source: [kernel.c]
+  app:
+    kind: exe
+    target: user
+    output: app.elf
+    deps: [kutil]
+    source: [app.c]
+"#;
+
+#[test]
+fn restricted_dependency() -> Result {
+    let proj = Project::new(MODULES)?;
+
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Dependency 'app' -> 'kutil' (in target 'user') is not allowed: module 'kutil' is visible only to [kernel]"));
+
+    Ok(())
+}
+
+#[test]
+fn allowed_dependency() -> Result {
+    let proj = Project::new(MODULES)?;
+    let allowed: Vec<&str> = MODULES.lines().take_while(|l| !l.starts_with("  app:")).collect();
+    proj.write("modules.yaml", &allowed.join("\n"))?;
+
+    proj.pb().arg("init").assert().success();
+
+    Ok(())
+}
+
+const NAMESPACED: &str = r#"
+name: test
+templates: templates
+subprojects:
+  - name: util
+    path: libs
+modules:
+  klog:
+    kind: lib
+    output: klog.a
+    visibility: private
+    source: []
+  kernel:
+    kind: exe
+    target: kernel
+    output: kernel.elf
+    depends: [klog, util.fmt]
+    source: []
+"#;
+
+const UTIL: &str = r#"
+name: util
+templates: templates
+modules:
+  fmt:
+    kind: lib
+    output: fmt.a
+    depends: [base, raw]
+    source: []
+  base:
+    kind: lib
+    output: base.a
+    visibility: [util]
+    source: []
+  raw:
+    kind: lib
+    output: raw.a
+    visibility: private
+    source: []
+"#;
+
+#[test]
+fn namespace_rules() -> Result {
+    // Private and namespace-restricted modules are usable within their
+    // own namespace
+    let proj = Project::new(NAMESPACED)?;
+    proj.write("libs/modules.yaml", UTIL)?;
+    proj.write("libs/templates/lib.default.j2", "")?;
+    proj.pb().arg("init").assert().success();
+
+    // But not from outside it
+    proj.write("modules.yaml", &NAMESPACED.replace("[klog, util.fmt]", "[klog, util.raw]"))?;
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "module 'util.raw' is private to namespace 'util'"));
+
+    proj.write("modules.yaml", &NAMESPACED.replace("[klog, util.fmt]", "[klog, util.base]"))?;
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "module 'util.base' is visible only to [util]"));
+
+    Ok(())
+}
+
+#[test]
+fn top_level_private_is_one_target() -> Result {
+    let proj = Project::new(&MODULES.replace("visibility: [kernel]", "visibility: private"))?;
+
+    proj.pb().arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Dependency 'app' -> 'kutil' (in target 'user') is not allowed: module 'kutil' is private to target 'kernel'"));
+
+    // Used from only one target, it's fine
+    proj.write("modules.yaml", &MODULES
+        .replace("visibility: [kernel]", "visibility: private")
+        .replace("    deps: [kutil]\n    source: [app.c]", "    source: [app.c]"))?;
+    proj.pb().arg("init").assert().success();
+
+    Ok(())
+}